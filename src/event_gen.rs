@@ -2,12 +2,26 @@ use arbitrary::{Arbitrary, Unstructured};
 use std::collections::HashSet;
 use scupt_util::message::Message;
 use scupt_util::node_id::NID;
-use crate::fuzzy_command::FuzzyCommand;
+use crate::fuzzy_clock::ClockFault;
+use crate::fuzzy_command::{DiskFault, DiskOp, FuzzyCommand};
 use crate::fuzzy_event::FuzzyEvent;
-use crate::fuzzy_setting::FuzzySetting;
+use crate::fuzzy_setting::{FuzzySetting, MessageRatio};
+use crate::payload_mutation::mutate_payload;
 
+/// Generate the fuzzy events for the incoming commands of the tested nodes.
+/// The message of a `MessageReqAck` request is passed as a `MessageReq` command,
+/// a `DiskReq` command expects a `Disk` event with the decision.
 pub trait FuzzyGenerator: Send + Sync {
-    fn gen(&self, cmd: FuzzyCommand) -> Vec<FuzzyEvent>;
+    /// Generate the events of `cmd` into `output`, drawing the decisions from the fuzz input `u`.
+    /// Return false when the fuzz input was exhausted.
+    fn gen(&self, cmd: FuzzyCommand, u: &mut Unstructured, output: &mut Vec<FuzzyEvent>) -> bool;
+
+    /// Generate the faults of the next step of the background fault timeline into `output`.
+    /// Return the milliseconds to wait after the previous step, None if the timeline ends,
+    /// in which case `output` is discarded.
+    fn gen_fault(&self, _u: &mut Unstructured, _output: &mut Vec<FuzzyEvent>) -> Option<u64> {
+        None
+    }
 }
 
 
@@ -33,10 +47,65 @@ impl EventGen {
             u:&mut Unstructured,
             vec:&mut Vec<FuzzyEvent>
     ) -> bool {
-        let r1 = fuzz_message_event(message, &self.setting, u, vec);
-        let r2 = fuzz_crash(message.dest(), &self.setting, u, vec);
+        let ratio = self.setting.message_ratio(message);
+        let r1 = fuzz_message_event(message, &self.setting, &ratio, u, vec);
+        let r2 = fuzz_crash(message.dest(), &self.setting, ratio.crash, u, vec);
         let r3 = fuzz_partition(&self.nodes, &self.setting, u, vec);
-        r1.is_ok() && r2.is_ok() && r3.is_ok()
+        let r4 = fuzz_link_failure(&self.nodes, &self.setting, u, vec);
+        let r5 = fuzz_clock(&self.nodes, &self.setting, u, vec);
+        r1.is_ok() && r2.is_ok() && r3.is_ok() && r4.is_ok() && r5.is_ok()
+    }
+
+    /// A step of the background fault timeline, a crash of any node of the setting and the
+    /// partitions, with the ratios of the setting.
+    /// Return an error if the fault timeline is disabled by a `fault_interval_max_ms` of 0, or
+    /// the fuzz input is empty, as `arbitrary` would pad it with zeros forever
+    pub fn fuzz_fault(
+        &self,
+        u:&mut Unstructured,
+        vec:&mut Vec<FuzzyEvent>
+    ) -> arbitrary::Result<u64> {
+        if self.setting.fault_interval_max_ms == 0 {
+            return Err(arbitrary::Error::IncorrectFormat);
+        }
+        if u.is_empty() {
+            return Err(arbitrary::Error::NotEnoughData);
+        }
+        let ms = u64::arbitrary(u)? % self.setting.fault_interval_max_ms;
+        let mut nids = if self.setting.node.is_empty() {
+            self.nodes.clone()
+        } else {
+            self.setting.node.clone()
+        };
+        nids.sort();
+        if !nids.is_empty() {
+            let node_id = nids[u32::arbitrary(u)? as usize % nids.len()];
+            fuzz_crash(node_id, &self.setting, self.setting.crash_ratio, u, vec)?;
+        }
+        fuzz_partition(&nids, &self.setting, u, vec)?;
+        fuzz_link_failure(&nids, &self.setting, u, vec)?;
+        Ok(ms)
+    }
+}
+
+impl FuzzyGenerator for EventGen {
+    fn gen(&self, cmd: FuzzyCommand, u: &mut Unstructured, output: &mut Vec<FuzzyEvent>) -> bool {
+        let ok = match cmd {
+            FuzzyCommand::MessageReq(m) => {
+                self.fuzz_message(&m, u, output)
+            }
+            FuzzyCommand::DiskReq(_, node_id, op) => {
+                fuzz_disk(node_id, op, &self.setting, u, output).is_ok()
+            }
+            _ => { true }
+        };
+        // `arbitrary` pads an exhausted input with zeros instead of failing, so an empty input is
+        // the end of the fuzz input
+        ok && !u.is_empty()
+    }
+
+    fn gen_fault(&self, u: &mut Unstructured, output: &mut Vec<FuzzyEvent>) -> Option<u64> {
+        self.fuzz_fault(u, output).ok()
     }
 }
 
@@ -58,24 +127,40 @@ fn delayed_message(
 fn fuzz_message_event(
     m:&Message<String>,
     setting: &FuzzySetting,
+    ratio: &MessageRatio,
     u:&mut Unstructured,
     output:&mut Vec<FuzzyEvent>
 ) -> arbitrary::Result<()> {
+    let is_lost = {
+        let n = u8::arbitrary(u)?;
+        (n as f64 / u8::MAX as f64)  < ratio.lost
+    };
+    if is_lost {
+        output.push(FuzzyEvent::Lost(m.clone()));
+        return Ok(());
+    }
 
     let is_delayed = {
         let n = u8::arbitrary(u)?;
-        (n as f64 / u8::MAX as f64)  < setting.message_delay_ratio
+        (n as f64 / u8::MAX as f64)  < ratio.delay
     };
-    if is_delayed {
-        let e = delayed_message(m, setting, u)?;
-        output.push(e);
+    let e = if is_delayed {
+        delayed_message(m, setting, u)?
     } else {
-        output.push(FuzzyEvent::Delay(0, m.clone()));
-    }
+        FuzzyEvent::Delay(0, m.clone())
+    };
+    let e = fuzz_mutation(e, setting, u)?;
+    let e = match e {
+        FuzzyEvent::Delay(0, m) if setting.message_reorder_window > 1 => {
+            FuzzyEvent::ReorderBuffer(setting.message_reorder_window, setting.message_reorder_flush_ms, m)
+        }
+        e => { e }
+    };
+    output.push(e);
 
     let is_repeated = {
         let n = u8::arbitrary(u)?;
-        (n as f64 / u8::MAX as f64)  < setting.message_repeat_ratio
+        (n as f64 / u8::MAX as f64)  < ratio.repeat
     };
     if is_repeated {
         let n = u64::arbitrary(u)?;
@@ -92,15 +177,42 @@ fn fuzz_message_event(
     Ok(())
 }
 
+/// Replace a delay event by a mutate event, which delivers the message with a mutated payload
+fn fuzz_mutation(
+    event:FuzzyEvent,
+    setting: &FuzzySetting,
+    u:&mut Unstructured,
+) -> arbitrary::Result<FuzzyEvent> {
+    let is_mutated = {
+        let n = u8::arbitrary(u)?;
+        (n as f64 / u8::MAX as f64)  < setting.message_mutation_ratio
+    };
+    if !is_mutated {
+        return Ok(event);
+    }
+    match event {
+        FuzzyEvent::Delay(ms, m) => {
+            match mutate_payload(m.clone().payload().as_str(), u)? {
+                Some(payload) => {
+                    Ok(FuzzyEvent::Mutate(ms, Message::new(payload, m.source(), m.dest())))
+                }
+                None => { Ok(FuzzyEvent::Delay(ms, m)) }
+            }
+        }
+        e => { Ok(e) }
+    }
+}
+
 fn fuzz_crash(
     node_id:NID,
     setting: &FuzzySetting,
+    crash_ratio: f64,
     u:&mut Unstructured,
     output:&mut Vec<FuzzyEvent>
 ) -> arbitrary::Result<()> {
     let is_crash = {
         let n = u8::arbitrary(u)?;
-        (n as f64 / u8::MAX as f64)  < setting.crash_ratio
+        (n as f64 / u8::MAX as f64)  < crash_ratio
     };
     if is_crash {
         let n = if setting.crash_restart_payload.len() == 0 || setting.restart_after_max_ms == 0{
@@ -119,6 +231,80 @@ fn fuzz_crash(
 }
 
 
+/// Always output a disk event, with `DiskFault::None` if no fault is injected, so every disk
+/// operation is recorded
+fn fuzz_disk(
+    node_id:NID,
+    op:DiskOp,
+    setting: &FuzzySetting,
+    u:&mut Unstructured,
+    output:&mut Vec<FuzzyEvent>
+) -> arbitrary::Result<()> {
+    let is_fault = {
+        let n = u8::arbitrary(u);
+        match n {
+            Ok(n) => { (n as f64 / u8::MAX as f64)  < setting.disk_fault_ratio }
+            Err(e) => {
+                output.push(FuzzyEvent::Disk(node_id, op, DiskFault::None));
+                return Err(e);
+            }
+        }
+    };
+    let fault = if is_fault {
+        match &op {
+            DiskOp::Write(len) => {
+                if *len == 0 {
+                    DiskFault::None
+                } else {
+                    DiskFault::TornWrite(u64::arbitrary(u).unwrap_or(0) % len)
+                }
+            }
+            DiskOp::Fsync => { DiskFault::FsyncError }
+            DiskOp::Crash => { DiskFault::LoseUnflushed }
+        }
+    } else {
+        DiskFault::None
+    };
+    output.push(FuzzyEvent::Disk(node_id, op, fault));
+    Ok(())
+}
+
+fn fuzz_clock(
+    node_ids:&Vec<NID>,
+    setting: &FuzzySetting,
+    u:&mut Unstructured,
+    output:&mut Vec<FuzzyEvent>
+) -> arbitrary::Result<()> {
+    let is_fault = {
+        let n = u8::arbitrary(u)?;
+        (n as f64 / u8::MAX as f64)  < setting.clock_fault_ratio
+    };
+    if !is_fault || node_ids.is_empty() {
+        return Ok(());
+    }
+    let mut nids = node_ids.clone();
+    nids.sort();
+    let node_id = nids[u32::arbitrary(u)? as usize % nids.len()];
+    let fault = if bool::arbitrary(u)? {
+        if setting.clock_max_skew_ms == 0 {
+            return Ok(());
+        }
+        let ms = (u64::arbitrary(u)? % setting.clock_max_skew_ms) as i64;
+        if bool::arbitrary(u)? {
+            ClockFault::Skew(ms)
+        } else {
+            ClockFault::Skew(-ms)
+        }
+    } else {
+        if setting.clock_max_pause_ms == 0 {
+            return Ok(());
+        }
+        ClockFault::Pause(u64::arbitrary(u)? % setting.clock_max_pause_ms)
+    };
+    output.push(FuzzyEvent::ClockFault(Message::new(fault.to_payload(), node_id, node_id)));
+    Ok(())
+}
+
 fn __partition(node_id:&Vec<NID>, n:u64, u:&mut Unstructured) -> arbitrary::Result<(Vec<NID>, Vec<NID>)> {
     let mut nids = node_id.clone();
     nids.sort();
@@ -153,13 +339,26 @@ fn fuzz_partition(
     setting: &FuzzySetting,
     u:&mut Unstructured,
     output:&mut Vec<FuzzyEvent>
+) -> arbitrary::Result<()> {
+    fuzz_partition_with_ratio(node_ids, setting.network_partition_ratio, false, setting, u, output)?;
+    fuzz_partition_with_ratio(node_ids, setting.network_one_way_partition_ratio, true, setting, u, output)?;
+    Ok(())
+}
+
+fn fuzz_partition_with_ratio(
+    node_ids:&Vec<NID>,
+    ratio:f64,
+    one_way:bool,
+    setting: &FuzzySetting,
+    u:&mut Unstructured,
+    output:&mut Vec<FuzzyEvent>
 ) -> arbitrary::Result<()> {
     let partition = {
         let n = u8::arbitrary(u)?;
-        (n as f64 / u8::MAX as f64)  < setting.network_partition_ratio
+        (n as f64 / u8::MAX as f64)  < ratio
     };
     if partition && node_ids.len() > 1 {
-        if node_ids.len() == 0 && setting.partition_end_after_max_ms == 0{
+        if setting.partition_end_after_max_ms == 0 {
             return Ok(())
         }
         let ms = u64::arbitrary(u)?;
@@ -171,8 +370,141 @@ fn fuzz_partition(
             return Ok(())
         }
         let (p1, p2) = __partition(node_ids, n, u)?;
-        output.push(FuzzyEvent::PartitionStart(p1.clone(), p2.clone()));
-        output.push(FuzzyEvent::PartitionRecovery(ms, p1.clone(), p2.clone()));
+        if one_way {
+            output.push(FuzzyEvent::OneWayPartitionStart(p1.clone(), p2.clone()));
+            output.push(FuzzyEvent::OneWayPartitionRecovery(ms, p1.clone(), p2.clone()));
+        } else {
+            output.push(FuzzyEvent::PartitionStart(p1.clone(), p2.clone()));
+            output.push(FuzzyEvent::PartitionRecovery(ms, p1.clone(), p2.clone()));
+        }
+    }
+    Ok(())
+}
+
+
+fn __multi_partition(node_id:&Vec<NID>, u:&mut Unstructured) -> arbitrary::Result<Vec<(NID, NID)>> {
+    let mut nids = node_id.clone();
+    nids.sort();
+    let num_group = u8::arbitrary(u)? as usize % (nids.len() - 1) + 2;
+    let mut group = vec![];
+    for _ in nids.iter() {
+        let g = u8::arbitrary(u)? as usize % num_group;
+        group.push(g);
+    }
+    let mut links = vec![];
+    for (i, id1) in nids.iter().enumerate() {
+        for (j, id2) in nids.iter().enumerate() {
+            if group[i] != group[j] {
+                links.push((*id1, *id2));
+            }
+        }
+    }
+    Ok(links)
+}
+
+/// fail an arbitrary set of links, each direction of a pair of nodes is drawn independently,
+/// so a link can fail one way only
+fn __link_failure(node_id:&Vec<NID>, u:&mut Unstructured) -> arbitrary::Result<Vec<(NID, NID)>> {
+    let mut nids = node_id.clone();
+    nids.sort();
+    let mut links = vec![];
+    for id1 in nids.iter() {
+        for id2 in nids.iter() {
+            if id1 != id2 && bool::arbitrary(u)? {
+                links.push((*id1, *id2));
+            }
+        }
+    }
+    Ok(links)
+}
+
+fn fuzz_link_failure(
+    node_ids:&Vec<NID>,
+    setting: &FuzzySetting,
+    u:&mut Unstructured,
+    output:&mut Vec<FuzzyEvent>
+) -> arbitrary::Result<()> {
+    for (ratio, multi_partition) in [
+        (setting.network_multi_partition_ratio, true),
+        (setting.network_link_failure_ratio, false)] {
+        let failure = {
+            let n = u8::arbitrary(u)?;
+            (n as f64 / u8::MAX as f64)  < ratio
+        };
+        if !failure || node_ids.len() <= 1 || setting.partition_end_after_max_ms == 0 {
+            continue;
+        }
+        let ms = u64::arbitrary(u)?;
+        let ms = ms % setting.partition_end_after_max_ms;
+        let links = if multi_partition {
+            __multi_partition(node_ids, u)?
+        } else {
+            __link_failure(node_ids, u)?
+        };
+        if links.is_empty() {
+            continue;
+        }
+        output.push(FuzzyEvent::LinkFailureStart(links.clone()));
+        output.push(FuzzyEvent::LinkFailureRecovery(ms, links));
     }
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use arbitrary::Unstructured;
+    use scupt_util::message::Message;
+
+    use crate::event_gen::{__link_failure, EventGen, FuzzyGenerator};
+    use crate::fuzzy_command::FuzzyCommand;
+    use crate::fuzzy_setting::FuzzySetting;
+
+    fn message() -> FuzzyCommand {
+        FuzzyCommand::MessageReq(Message::new("{}".to_string(), 1, 2))
+    }
+
+    #[test]
+    fn test_gen_end_of_input() {
+        let gen = EventGen::new(vec![1, 2, 3], FuzzySetting::default());
+        let data = [0u8; 2];
+        let mut u = Unstructured::new(&data);
+        let mut output = vec![];
+        assert!(!gen.gen(message(), &mut u, &mut output));
+
+        let data = [0u8; 1024];
+        let mut u = Unstructured::new(&data);
+        let mut output = vec![];
+        assert!(gen.gen(message(), &mut u, &mut output));
+        assert!(!output.is_empty());
+    }
+
+    #[test]
+    fn test_fault_end_of_input() {
+        let mut setting = FuzzySetting::default();
+        setting.fault_interval_max_ms = 100;
+        let gen = EventGen::new(vec![1, 2, 3], setting);
+        let data = [7u8; 64];
+        let mut u = Unstructured::new(&data);
+        let mut steps = 0;
+        loop {
+            let mut output = vec![];
+            match gen.gen_fault(&mut u, &mut output) {
+                Some(_) => { steps += 1; }
+                None => { break; }
+            }
+            assert!(steps <= data.len());
+        }
+        assert!(steps > 0);
+    }
+
+    #[test]
+    fn test_link_failure_one_way() {
+        let nodes = vec![2, 1];
+        let mut u = Unstructured::new(&[1, 0]);
+        assert_eq!(__link_failure(&nodes, &mut u).unwrap(), vec![(1, 2)]);
+        let mut u = Unstructured::new(&[0, 1]);
+        assert_eq!(__link_failure(&nodes, &mut u).unwrap(), vec![(2, 1)]);
+        let mut u = Unstructured::new(&[1, 1]);
+        assert_eq!(__link_failure(&nodes, &mut u).unwrap(), vec![(1, 2), (2, 1)]);
+    }
+}
@@ -0,0 +1,216 @@
+use std::collections::{HashMap, HashSet};
+use std::sync::Mutex;
+
+use scupt_util::node_id::NID;
+
+/// The fault model the tested protocol claims to tolerate.
+/// The faults are admitted when they execute, not when they are generated, so a restart or a
+/// recovery frees the budget for the later faults. A crash or a partition exceeding the budget
+/// is discarded, and so is the restart or the recovery following it, so the schedules stay
+/// within the fault model.
+/// The minority of a partition is computed on the links disconnected by all the partitions and
+/// link failures admitted by the budget, as the nodes out of the largest group of the nodes
+/// still connected to each other in both directions.
+/// A limit of 0 is unbounded.
+pub struct FaultBudget {
+    nodes: Vec<NID>,
+    max_crashed: u64,
+    max_minority: u64,
+    max_total: u64,
+    inner: Mutex<FaultBudgetInner>,
+}
+
+struct FaultBudgetInner {
+    /// the nodes crashed and not yet restarted
+    crashed: HashSet<NID>,
+    /// number of the crashes and partitions of the run
+    total: u64,
+    /// the links disconnected by the admitted failures, and the number of the failures
+    /// disconnecting each link
+    disconnected: HashMap<(NID, NID), u64>,
+    /// the nodes of the discarded crashes, whose restarts are discarded
+    discarded_crash: Vec<NID>,
+    /// the sorted links of the discarded partitions and link failures, whose recoveries are
+    /// discarded
+    discarded_links: Vec<Vec<(NID, NID)>>,
+}
+
+impl FaultBudget {
+    pub fn new(nodes: Vec<NID>, max_crashed: u64, max_minority: u64, max_total: u64) -> Self {
+        Self {
+            nodes,
+            max_crashed,
+            max_minority,
+            max_total,
+            inner: Mutex::new(FaultBudgetInner {
+                crashed: Default::default(),
+                total: 0,
+                disconnected: Default::default(),
+                discarded_crash: vec![],
+                discarded_links: vec![],
+            }),
+        }
+    }
+
+    /// Admit the crash of `node_id`, when it executes.
+    /// Return false if the crash is discarded, the restart following it is then discarded too
+    pub fn admit_crash(&self, node_id: NID) -> bool {
+        let mut inner = self.inner.lock().unwrap();
+        let admitted = !inner.crashed.contains(&node_id)
+            && self.within(self.max_crashed, inner.crashed.len() as u64)
+            && self.within(self.max_total, inner.total);
+        if admitted {
+            let _ = inner.crashed.insert(node_id);
+            inner.total += 1;
+        } else {
+            inner.discarded_crash.push(node_id);
+        }
+        admitted
+    }
+
+    /// Admit the restart of `node_id`, when it executes.
+    /// Return false if the restart follows a discarded crash
+    pub fn admit_restart(&self, node_id: NID) -> bool {
+        let mut inner = self.inner.lock().unwrap();
+        if let Some(i) = inner.discarded_crash.iter().position(|n| { *n == node_id }) {
+            let _ = inner.discarded_crash.remove(i);
+            return false;
+        }
+        let _ = inner.crashed.remove(&node_id);
+        true
+    }
+
+    /// A budget with the same limits, no fault admitted yet
+    pub fn with_same_limits(&self) -> Self {
+        Self::new(self.nodes.clone(), self.max_crashed, self.max_minority, self.max_total)
+    }
+
+    /// Admit a partition or a link failure disconnecting `links`, when it executes.
+    /// Return false if the failure is discarded, the recovery following it is then discarded too
+    pub fn admit_failure(&self, links: &Vec<(NID, NID)>) -> bool {
+        let mut inner = self.inner.lock().unwrap();
+        let mut all: HashSet<(NID, NID)> = inner.disconnected.keys().cloned().collect();
+        all.extend(links.iter().cloned());
+        let admitted = self.within(self.max_total, inner.total)
+            && (self.max_minority == 0 || self.minority(&all) <= self.max_minority);
+        if admitted {
+            inner.total += 1;
+            for link in links.iter() {
+                *inner.disconnected.entry(*link).or_insert(0) += 1;
+            }
+        } else {
+            inner.discarded_links.push(Self::sorted(links));
+        }
+        admitted
+    }
+
+    /// Admit the recovery of `links`, when it executes.
+    /// Return false if the recovery follows a discarded partition or link failure
+    pub fn admit_recovery(&self, links: &Vec<(NID, NID)>) -> bool {
+        let mut inner = self.inner.lock().unwrap();
+        let links = Self::sorted(links);
+        if let Some(i) = inner.discarded_links.iter().position(|l| { *l == links }) {
+            let _ = inner.discarded_links.remove(i);
+            return false;
+        }
+        for link in links.iter() {
+            let remove = match inner.disconnected.get_mut(link) {
+                Some(n) => {
+                    *n -= 1;
+                    *n == 0
+                }
+                None => { false }
+            };
+            if remove {
+                let _ = inner.disconnected.remove(link);
+            }
+        }
+        true
+    }
+
+    /// The number of the nodes out of the largest group of the nodes connected to each other in
+    /// both directions, when `disconnected` are disconnected
+    pub fn minority(&self, disconnected: &HashSet<(NID, NID)>) -> u64 {
+        let mut group: HashMap<NID, usize> = HashMap::new();
+        let mut size = vec![];
+        for n in self.nodes.iter() {
+            if group.contains_key(n) {
+                continue;
+            }
+            let g = size.len();
+            let mut count = 0;
+            let mut stack = vec![*n];
+            let _ = group.insert(*n, g);
+            while let Some(i) = stack.pop() {
+                count += 1;
+                for j in self.nodes.iter() {
+                    if group.contains_key(j)
+                        || disconnected.contains(&(i, *j))
+                        || disconnected.contains(&(*j, i)) {
+                        continue;
+                    }
+                    let _ = group.insert(*j, g);
+                    stack.push(*j);
+                }
+            }
+            size.push(count);
+        }
+        let largest = size.iter().copied().max().unwrap_or(0);
+        (self.nodes.len() - largest) as u64
+    }
+
+    fn sorted(links: &Vec<(NID, NID)>) -> Vec<(NID, NID)> {
+        let mut links = links.clone();
+        links.sort();
+        links
+    }
+
+    fn within(&self, max: u64, n: u64) -> bool {
+        max == 0 || n < max
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use scupt_util::node_id::NID;
+
+    use crate::fault_budget::FaultBudget;
+
+    #[test]
+    fn test_max_crashed() {
+        let budget = FaultBudget::new(vec![1, 2, 3], 1, 0, 0);
+        assert!(budget.admit_crash(1));
+
+        // node 1 is still crashed, the crash of node 2 and its restart are discarded
+        assert!(!budget.admit_crash(2));
+        assert!(!budget.admit_restart(2));
+
+        // the restart of node 1 frees the budget
+        assert!(budget.admit_restart(1));
+        assert!(budget.admit_crash(2));
+        assert!(budget.admit_restart(2));
+    }
+
+    #[test]
+    fn test_max_minority() {
+        let budget = FaultBudget::new(vec![1, 2, 3], 0, 1, 0);
+        let none = HashSet::new();
+        assert_eq!(budget.minority(&none), 0);
+        let partition: Vec<(NID, NID)> = vec![(1, 2), (1, 3), (2, 1), (3, 1)];
+        assert!(budget.admit_failure(&partition));
+
+        // the link failure leaves every node in a singleton group, a minority of 2 nodes
+        let link_failure: Vec<(NID, NID)> = vec![(2, 3), (3, 2)];
+        assert!(!budget.admit_failure(&link_failure));
+        assert!(!budget.admit_recovery(&vec![(3, 2), (2, 3)]));
+
+        // a one-way link failure also splits the group, until the partition recovered
+        let one_way: Vec<(NID, NID)> = vec![(2, 3)];
+        assert!(!budget.admit_failure(&one_way));
+        assert!(!budget.admit_recovery(&one_way));
+        assert!(budget.admit_recovery(&partition));
+        assert!(budget.admit_failure(&one_way));
+    }
+}
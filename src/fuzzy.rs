@@ -1,25 +1,74 @@
 use std::any::Any;
+use std::fs::File;
+use std::io::{BufWriter, Write};
 use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
 use lazy_static::lazy_static;
+use rusqlite::Connection;
 use scc::HashMap as ConcurrentHashMap;
 use scupt_net::notifier::Notifier;
+use scupt_util::error_type::ET;
 use scupt_util::message::{Message, MsgTrait};
 use scupt_util::node_id::NID;
+use scupt_util::res::Res;
+use scupt_util::res_of::res_sqlite;
 use scupt_util::serde_json_value::SerdeJsonValue;
+use tracing::error;
 
-use crate::fuzzy_client::FuzzyClient;
-use crate::fuzzy_command::FuzzyCmdType;
+use crate::fuzzy_clock::ClockFault;
+use crate::fuzzy_client::{FuzzyClient, FuzzyClientStatus, FuzzyUnreachablePolicy};
+use crate::fuzzy_command::{DiskFault, DiskOp, FuzzyCmdType, FuzzyDecision};
 
 pub trait FEventMsgHandler: Send + Sync + Any {
     fn on_handle(&self, name: String, message: Message<String>);
 }
 
+/// Safety invariant over an event sequence
+pub trait FInvariant: Send + Sync {
+    fn name(&self) -> String;
+
+    /// return false if the event sequence violates the invariant
+    fn check(&self, sequence: &Vec<SerdeJsonValue>) -> bool;
+}
+
+/// An invariant violation
+#[derive(Clone, Debug)]
+pub struct InvariantViolation {
+    /// name of the event sequence
+    pub sequence: String,
+
+    /// name of the violated invariant
+    pub invariant: String,
+
+    /// the event sequence prefix violating the invariant
+    pub prefix: Vec<SerdeJsonValue>,
+
+    /// the action id of the last delivery received by the node of the sequence,
+    /// None if no delivery was received
+    pub action_id: Option<u64>,
+}
+
 lazy_static! {
     static ref EVENT_HANDLER : ConcurrentHashMap<String, Arc<dyn  FEventMsgHandler>> = ConcurrentHashMap::new();
     static ref FUZZY : ConcurrentHashMap<String, FuzzyClient> = ConcurrentHashMap::new();
     static ref EVENT:ConcurrentHashMap<String, Arc<Mutex<Vec<SerdeJsonValue>>>> =
         ConcurrentHashMap::new();
+    static ref INVARIANT:ConcurrentHashMap<String, Arc<Mutex<Vec<Arc<dyn FInvariant>>>>> =
+        ConcurrentHashMap::new();
+    static ref VIOLATION:ConcurrentHashMap<String, Arc<Mutex<Vec<InvariantViolation>>>> =
+        ConcurrentHashMap::new();
+    static ref ACTION_ID:ConcurrentHashMap<String, u64> = ConcurrentHashMap::new();
+    static ref CLOCK:ConcurrentHashMap<String, Arc<Mutex<FuzzyClock>>> = ConcurrentHashMap::new();
+}
+
+/// The fuzzed clock of a tested node
+#[derive(Default)]
+struct FuzzyClock {
+    /// skewed milliseconds from the real time, set by the last skew fault
+    skew_ms: i64,
+    /// the frozen time in milliseconds, and the real time when the pause ends
+    pause: Option<(u64, Instant)>,
 }
 
 
@@ -31,6 +80,160 @@ pub fn event_sequence_setup(s: &str, handle: Arc<dyn FEventMsgHandler>) {
 
 pub fn event_sequence_unset(s: &str) {
     let _ = EVENT.remove(&s.to_string());
+    let _ = INVARIANT.remove(&s.to_string());
+    let _ = VIOLATION.remove(&s.to_string());
+}
+
+/// Return the events of the sequence `s`
+pub fn event_sequence_get(s: &str) -> Vec<SerdeJsonValue> {
+    let opt = EVENT.get(&s.to_string());
+    match opt {
+        Some(v) => {
+            let seq = v.get().clone();
+            let _g = seq.lock().unwrap();
+            _g.clone()
+        }
+        None => { vec![] }
+    }
+}
+
+/// Dump the events of the sequence `s` to the file `path`, one JSON event per line
+pub fn event_sequence_dump_json_lines(s: &str, path: &str) -> Res<()> {
+    let seq = event_sequence_get(s);
+    let file = File::create(path).map_err(|e| { ET::IOError(e.to_string()) })?;
+    let mut writer = BufWriter::new(file);
+    for e in seq.iter() {
+        let line = serde_json::to_string(e).map_err(|e| { ET::FatalError(e.to_string()) })?;
+        writeln!(writer, "{}", line).map_err(|e| { ET::IOError(e.to_string()) })?;
+    }
+    writer.flush().map_err(|e| { ET::IOError(e.to_string()) })?;
+    Ok(())
+}
+
+/// Dump the events of the sequence `s` to the table `event_sequence` of the SQLite database
+/// `path`, which can be the database written by the fuzzy server.
+/// The events previously dumped for `s` are replaced.
+pub fn event_sequence_dump_sqlite(s: &str, path: &str) -> Res<()> {
+    let seq = event_sequence_get(s);
+    let mut conn = res_sqlite(Connection::open(path))?;
+    let trans = res_sqlite(conn.transaction())?;
+    let _r = trans.execute(
+        r#"create table if not exists event_sequence (
+                name text not null,
+                seq integer not null,
+                event text not null,
+                primary key (name, seq)
+            );"#, ());
+    res_sqlite(_r)?;
+    let _r = trans.execute(
+        r#"delete from event_sequence where name = ?1"#, (s,));
+    res_sqlite(_r)?;
+    for (i, e) in seq.iter().enumerate() {
+        let event = serde_json::to_string(e).map_err(|e| { ET::FatalError(e.to_string()) })?;
+        let _r = trans.execute(
+            r#"insert into event_sequence(name, seq, event)
+                   values(?1, ?2, ?3)"#, (s, &(i as u64), &event));
+        res_sqlite(_r)?;
+    }
+    res_sqlite(trans.commit())?;
+    Ok(())
+}
+
+/// Add an invariant, which is checked each time an event is added to the sequence `s`.
+/// An invariant is no longer checked after it was violated.
+pub fn event_invariant_add(s: &str, invariant: Arc<dyn FInvariant>) {
+    let name = s.to_string();
+    let _ = INVARIANT.insert(name.clone(), Arc::new(Mutex::new(vec![])));
+    let _ = VIOLATION.insert(name.clone(), Arc::new(Mutex::new(vec![])));
+    let opt = INVARIANT.get(&name);
+    match opt {
+        Some(v) => {
+            let vec = v.get().clone();
+            let mut _g = vec.lock().unwrap();
+            _g.push(invariant);
+        }
+        None => {}
+    }
+}
+
+/// Return the invariant violations of the sequence `s`
+pub fn event_invariant_violation(s: &str) -> Vec<InvariantViolation> {
+    let opt = VIOLATION.get(&s.to_string());
+    match opt {
+        Some(v) => {
+            let vec = v.get().clone();
+            let _g = vec.lock().unwrap();
+            _g.clone()
+        }
+        None => { vec![] }
+    }
+}
+
+/// Set the action id of the last delivery received by the node of the sequence `s`
+pub fn fuzzy_action_id_set(s: &str, id: u64) {
+    let name = s.to_string();
+    let _ = ACTION_ID.remove(&name);
+    let _ = ACTION_ID.insert(name, id);
+}
+
+/// The action id of the last delivery received by the node of the sequence `s`
+pub fn fuzzy_action_id(s: &str) -> Option<u64> {
+    let opt = ACTION_ID.get(&s.to_string());
+    match opt {
+        Some(v) => { Some(*v.get()) }
+        None => { None }
+    }
+}
+
+/// Set the action id of the sequence `s` from the JSON of a message received from the fuzzy
+/// server, the server tags each delivered message by its action id if `tag_action_id` of the
+/// setting is enabled.
+/// Return the action id, None if the message was not tagged
+pub fn fuzzy_received(s: &str, json: &str) -> Option<u64> {
+    let opt = fuzzy_delivery_action_id(json);
+    if let Some(id) = opt {
+        fuzzy_action_id_set(s, id);
+    }
+    opt
+}
+
+/// The action id tagging the JSON of a message delivered by the fuzzy server
+pub fn fuzzy_delivery_action_id(json: &str) -> Option<u64> {
+    let value: serde_json::Value = serde_json::from_str(json).ok()?;
+    value.get(DELIVERY_ACTION_ID)?.as_u64()
+}
+
+/// The field tagging the JSON of a delivered message by its action id
+pub const DELIVERY_ACTION_ID: &str = "fuzzy_action_id";
+
+fn event_invariant_check(s: &str, sequence: &Vec<SerdeJsonValue>) {
+    let name = s.to_string();
+    let invariants = match INVARIANT.get(&name) {
+        Some(v) => { v.get().clone() }
+        None => { return; }
+    };
+    let violations = match VIOLATION.get(&name) {
+        Some(v) => { v.get().clone() }
+        None => { return; }
+    };
+    let invariants = invariants.lock().unwrap();
+    let mut violations = violations.lock().unwrap();
+    for i in invariants.iter() {
+        let invariant = i.name();
+        if violations.iter().any(|v| { v.invariant == invariant }) {
+            continue;
+        }
+        if !i.check(sequence) {
+            let action_id = fuzzy_action_id(s);
+            error!("event sequence {} violates invariant {}, action id {:?}", name, invariant, action_id);
+            violations.push(InvariantViolation {
+                sequence: name.clone(),
+                invariant,
+                prefix: sequence.clone(),
+                action_id,
+            });
+        }
+    }
 }
 
 pub fn event_sequence_add<M: MsgTrait + 'static>(s: &str, e: Message<M>) {
@@ -51,9 +254,10 @@ pub fn event_sequence_add<M: MsgTrait + 'static>(s: &str, e: Message<M>) {
     let sjv = SerdeJsonValue::new(v);
     match opt {
         Some(e) => {
-            let s = e.get().clone();
-            let mut seq = s.lock().unwrap();
+            let _s = e.get().clone();
+            let mut seq = _s.lock().unwrap();
             seq.push(sjv);
+            event_invariant_check(s, &seq);
         }
         None => { return; }
     };
@@ -80,6 +284,36 @@ macro_rules! event_unset {
     };
 }
 
+/// Event sequence invariant
+#[macro_export]
+macro_rules! event_invariant {
+    ($name:expr, $invariant:expr) => {
+        {
+            scupt_fuzzy::fuzzy::event_invariant_add($name, $invariant);
+        }
+    };
+}
+
+/// Dump an event sequence to a JSON lines file
+#[macro_export]
+macro_rules! event_dump_json_lines {
+    ($name:expr, $path:expr) => {
+        {
+            scupt_fuzzy::fuzzy::event_sequence_dump_json_lines($name, $path)
+        }
+    };
+}
+
+/// Dump an event sequence to a SQLite database
+#[macro_export]
+macro_rules! event_dump_sqlite {
+    ($name:expr, $path:expr) => {
+        {
+            scupt_fuzzy::fuzzy::event_sequence_dump_sqlite($name, $path)
+        }
+    };
+}
+
 /// Event sequence unset
 #[macro_export]
 macro_rules! event_add {
@@ -91,11 +325,33 @@ macro_rules! event_add {
 }
 
 pub fn fuzzy_testing_setup(name: &str, id: NID, addr: String) {
+    fuzzy_testing_setup_with_policy(name, id, addr, FuzzyUnreachablePolicy::default());
+}
+
+/// Fuzzy testing setup, `policy` decides what to do when the fuzzy server is unreachable
+pub fn fuzzy_testing_setup_with_policy(name: &str, id: NID, addr: String, policy: FuzzyUnreachablePolicy) {
     let name = name.to_string();
-    let client = FuzzyClient::new(id, name.clone(), addr, Notifier::new()).unwrap();
+    let client = FuzzyClient::new_with_policy(id, name.clone(), addr, Notifier::new(), policy).unwrap();
     let _ = FUZZY.insert(name, client);
 }
 
+/// The status of the fuzzy testing client, None if fuzzy testing is not enabled
+pub fn fuzzy_testing_status(name: &str) -> Option<FuzzyClientStatus> {
+    fuzzy_client(name).map(|c| { c.status() })
+}
+
+/// The client of `name`, cloned out of the map, so the entry is not locked while a request is
+/// awaited
+fn fuzzy_client(name: &str) -> Option<FuzzyClient> {
+    FUZZY.read(&name.to_string(), |_, c| { c.clone() })
+}
+
+/// Log the error of a request, an unreachable server is then reported by
+/// `fuzzy_testing_status`
+fn fuzzy_testing_error(name: &str, e: &ET) {
+    error!("fuzzy testing {} request error: {:?}", name, e);
+}
+
 pub fn fuzzy_testing_enable(name: &str) -> bool {
     FUZZY.contains(&name.to_string())
 }
@@ -104,19 +360,132 @@ pub fn fuzzy_testing_unset(name: &str) {
     let _ = FUZZY.remove(&name.to_string());
     let _ = EVENT.remove(&name.to_string());
     let _ = EVENT_HANDLER.remove(&name.to_string());
+    let _ = INVARIANT.remove(&name.to_string());
+    let _ = VIOLATION.remove(&name.to_string());
+    let _ = CLOCK.remove(&name.to_string());
+    let _ = ACTION_ID.remove(&name.to_string());
 }
 
+/// Send a message to the fuzzy server.
+/// An error of the request is logged, use `fuzzy_testing_message_res` to handle it
 pub async fn fuzzy_testing_message<M: MsgTrait + 'static>(name: &str, message: Message<M>) {
-    let opt = FUZZY.get(&name.to_string());
+    if let Err(e) = fuzzy_testing_message_res(name, message).await {
+        fuzzy_testing_error(name, &e);
+    }
+}
+
+/// Send a message to the fuzzy server.
+/// Return the error of the request, such as an unreachable server with the `FailFast` policy
+pub async fn fuzzy_testing_message_res<M: MsgTrait + 'static>(name: &str, message: Message<M>) -> Res<()> {
+    let opt = fuzzy_client(name);
     match opt {
-        Some(v) => {
-            let _ = v.get().fuzzy_rpc(FuzzyCmdType::MessageReq, message).await;
+        Some(c) => {
+            let _ = c.fuzzy_rpc(FuzzyCmdType::MessageReq, message).await?;
+            Ok(())
+        }
+        None => { Ok(()) }
+    }
+}
+
+/// Send a message to the fuzzy server and await the decision of the server.
+/// Return None if fuzzy testing is not enabled, or the request failed, the error is logged
+pub async fn fuzzy_testing_message_ack<M: MsgTrait + 'static>(name: &str, message: Message<M>) -> Option<FuzzyDecision> {
+    match fuzzy_testing_message_ack_res(name, message).await {
+        Ok(opt) => { opt }
+        Err(e) => {
+            fuzzy_testing_error(name, &e);
+            None
         }
-        None => {}
     }
 }
 
+/// Send a message to the fuzzy server and await the decision of the server.
+/// Return None if fuzzy testing is not enabled, or the error of the request
+pub async fn fuzzy_testing_message_ack_res<M: MsgTrait + 'static>(name: &str, message: Message<M>) -> Res<Option<FuzzyDecision>> {
+    let opt = fuzzy_client(name);
+    match opt {
+        Some(c) => { c.fuzzy_rpc(FuzzyCmdType::MessageReqAck, message).await }
+        None => { Ok(None) }
+    }
+}
 
+/// Ask the fuzzy server which fault to inject to a disk operation of node `id`.
+/// Return `DiskFault::None` if fuzzy testing is not enabled, or the request failed, the error
+/// is logged
+pub async fn fuzzy_testing_disk(name: &str, id: NID, op: DiskOp) -> DiskFault {
+    match fuzzy_testing_disk_res(name, id, op).await {
+        Ok(fault) => { fault }
+        Err(e) => {
+            fuzzy_testing_error(name, &e);
+            DiskFault::None
+        }
+    }
+}
+
+/// Ask the fuzzy server which fault to inject to a disk operation of node `id`.
+/// Return `DiskFault::None` if fuzzy testing is not enabled, or the error of the request
+pub async fn fuzzy_testing_disk_res(name: &str, id: NID, op: DiskOp) -> Res<DiskFault> {
+    let opt = fuzzy_client(name);
+    match opt {
+        Some(c) => {
+            let r = c.fuzzy_disk_rpc(id, op).await?;
+            match r {
+                Some(FuzzyDecision::Disk(action_id, fault)) => {
+                    fuzzy_action_id_set(name, action_id);
+                    Ok(fault)
+                }
+                _ => { Ok(DiskFault::None) }
+            }
+        }
+        None => { Ok(DiskFault::None) }
+    }
+}
+
+/// Apply the clock fault of a payload received from the fuzzy server.
+/// Return false if the payload is not a clock fault command, which is then to be handled by
+/// the node.
+pub fn fuzzy_clock_handle(name: &str, payload: &str) -> bool {
+    let fault = match ClockFault::from_payload(payload) {
+        Some(f) => { f }
+        None => { return false; }
+    };
+    let now_ms = fuzzy_clock_now_ms(name);
+    let _ = CLOCK.insert(name.to_string(), Arc::new(Mutex::new(FuzzyClock::default())));
+    let opt = CLOCK.get(&name.to_string());
+    if let Some(v) = opt {
+        let clock = v.get().clone();
+        let mut _g = clock.lock().unwrap();
+        match fault {
+            ClockFault::Skew(ms) => {
+                _g.skew_ms = ms;
+            }
+            ClockFault::Pause(ms) => {
+                _g.pause = Some((now_ms, Instant::now() + Duration::from_millis(ms)));
+            }
+        }
+    }
+    true
+}
+
+/// The fuzzed clock of `name`, in milliseconds since the UNIX epoch
+pub fn fuzzy_clock_now_ms(name: &str) -> u64 {
+    let real_ms = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_millis() as u64;
+    let opt = CLOCK.get(&name.to_string());
+    match opt {
+        Some(v) => {
+            let clock = v.get().clone();
+            let mut _g = clock.lock().unwrap();
+            if let Some((frozen_ms, end)) = _g.pause {
+                if Instant::now() < end {
+                    return frozen_ms;
+                }
+                _g.pause = None;
+            }
+            real_ms.saturating_add_signed(_g.skew_ms)
+        }
+        None => { real_ms }
+    }
+}
 
 /// Fuzzy testing setup
 #[macro_export]
@@ -128,6 +497,16 @@ macro_rules! fuzzy_test_setup {
     };
 }
 
+/// Fuzzy testing setup, with a policy for an unreachable fuzzy server
+#[macro_export]
+macro_rules! fuzzy_test_setup_with_policy {
+    ($name:expr, $id:expr, $addr:expr, $policy:expr) => {
+        {
+            scupt_fuzzy::fuzzy::fuzzy_testing_setup_with_policy($name, $id, $addr, $policy);
+        }
+    };
+}
+
 /// Fuzzy testing unset
 #[macro_export]
 macro_rules! fuzzy_test_unset {
@@ -147,6 +526,76 @@ macro_rules! fuzzy_message {
     };
 }
 
+/// Send a message to the fuzzy server, return the error of the request
+#[macro_export]
+macro_rules! fuzzy_message_res {
+    ($name:expr, $message:expr) => {
+        {
+            scupt_fuzzy::fuzzy::fuzzy_testing_message_res($name, $message).await
+        }
+    };
+}
+
+/// Send a message to the fuzzy server and await the decision
+#[macro_export]
+macro_rules! fuzzy_message_ack {
+    ($name:expr, $message:expr) => {
+        {
+            scupt_fuzzy::fuzzy::fuzzy_testing_message_ack($name, $message).await
+        }
+    };
+}
+
+/// Send a message to the fuzzy server and await the decision, return the error of the request
+#[macro_export]
+macro_rules! fuzzy_message_ack_res {
+    ($name:expr, $message:expr) => {
+        {
+            scupt_fuzzy::fuzzy::fuzzy_testing_message_ack_res($name, $message).await
+        }
+    };
+}
+
+/// Set the action id of the event sequence from a message received from the fuzzy server
+#[macro_export]
+macro_rules! fuzzy_received {
+    ($name:expr, $json:expr) => {
+        {
+            scupt_fuzzy::fuzzy::fuzzy_received($name, $json)
+        }
+    };
+}
+
+/// The disk fault to inject to a disk operation
+#[macro_export]
+macro_rules! fuzzy_disk {
+    ($name:expr, $id:expr, $op:expr) => {
+        {
+            scupt_fuzzy::fuzzy::fuzzy_testing_disk($name, $id, $op).await
+        }
+    };
+}
+
+/// The disk fault to inject to a disk operation, return the error of the request
+#[macro_export]
+macro_rules! fuzzy_disk_res {
+    ($name:expr, $id:expr, $op:expr) => {
+        {
+            scupt_fuzzy::fuzzy::fuzzy_testing_disk_res($name, $id, $op).await
+        }
+    };
+}
+
+/// The fuzzed clock in milliseconds since the UNIX epoch
+#[macro_export]
+macro_rules! fuzzy_clock_now {
+    ($name:expr) => {
+        {
+            scupt_fuzzy::fuzzy::fuzzy_clock_now_ms($name)
+        }
+    };
+}
+
 /// Is an automation enable
 #[macro_export]
 macro_rules! fuzzy_init {
@@ -0,0 +1,132 @@
+use std::fs;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use scupt_util::error_type::ET;
+use scupt_util::res::Res;
+use tracing::info;
+
+use crate::fuzzy_corpus::{FuzzyCorpus, Verdict};
+use crate::fuzzy_session::FuzzySession;
+use crate::fuzzy_shrink::FailurePredicate;
+
+/// Options of a campaign, by default the campaign stops at the first session not passed
+#[derive(Clone, Debug)]
+pub struct CampaignOpt {
+    /// directory of the databases of the sessions
+    pub db_dir: String,
+
+    /// stop the campaign after some milliseconds, 0 is unbounded
+    pub time_budget_ms: u64,
+
+    /// the verdict of a session not ended after some milliseconds is a timeout, 0 is unbounded
+    pub session_timeout_ms: u64,
+
+    /// stop the campaign at the first session not passed, unset to collect the verdicts of all
+    /// the inputs
+    pub stop_on_failure: bool,
+}
+
+impl Default for CampaignOpt {
+    fn default() -> Self {
+        Self {
+            db_dir: Default::default(),
+            time_budget_ms: 0,
+            session_timeout_ms: 0,
+            stop_on_failure: true,
+        }
+    }
+}
+
+/// The outcome of a session of a campaign
+#[derive(Clone, Debug)]
+pub struct SessionVerdict {
+    pub session: u64,
+    pub db_path: String,
+    pub verdict: Verdict,
+}
+
+/// A campaign runs a session for each input back-to-back, the session restarts the cluster by
+/// its lifecycle hook before each input.
+/// The verdict of a session ended in time is an invariant violation if the predicate fails.
+/// Each session records its fuzzy events in its own database, and the verdicts are collected,
+/// and stored in the corpus if one is set.
+pub struct FuzzyCampaign {
+    session: Arc<FuzzySession>,
+    predicate: Arc<dyn FailurePredicate>,
+    corpus: Option<Arc<FuzzyCorpus>>,
+    opt: CampaignOpt,
+}
+
+impl FuzzyCampaign {
+    pub fn new(
+        session: Arc<FuzzySession>,
+        predicate: Arc<dyn FailurePredicate>,
+        corpus: Option<Arc<FuzzyCorpus>>,
+        opt: CampaignOpt,
+    ) -> Self {
+        Self {
+            session,
+            predicate,
+            corpus,
+            opt,
+        }
+    }
+
+    /// Run a session for each input, until the inputs ran out, the time budget was spent, or
+    /// a session failed, unless `stop_on_failure` is unset.
+    /// The cluster is stopped after the campaign, even if a session stopped on an error.
+    /// Return the verdicts of the sessions run
+    pub fn run<I: IntoIterator<Item=Vec<u8>>>(&self, inputs: I) -> Res<Vec<SessionVerdict>> {
+        let r = self.run_sessions(inputs);
+        let stopped = self.session.stop();
+        let verdicts = r?;
+        stopped?;
+        Ok(verdicts)
+    }
+
+    fn run_sessions<I: IntoIterator<Item=Vec<u8>>>(&self, inputs: I) -> Res<Vec<SessionVerdict>> {
+        fs::create_dir_all(&self.opt.db_dir).map_err(|e| { ET::IOError(e.to_string()) })?;
+        let start = Instant::now();
+        let mut verdicts = vec![];
+        for (i, input) in inputs.into_iter().enumerate() {
+            if self.opt.time_budget_ms > 0
+                && start.elapsed() >= Duration::from_millis(self.opt.time_budget_ms) {
+                info!("campaign time budget spent after {} sessions", i);
+                break;
+            }
+            let verdict = self.run_session(i as u64, &input)?;
+            let failed = verdict.verdict != Verdict::Pass;
+            info!("campaign session {}, verdict {:?}", i, verdict.verdict);
+            verdicts.push(verdict);
+            if failed && self.opt.stop_on_failure {
+                break;
+            }
+        }
+        Ok(verdicts)
+    }
+
+    fn run_session(&self, session: u64, input: &[u8]) -> Res<SessionVerdict> {
+        let db_path = Path::new(&self.opt.db_dir)
+            .join(format!("session_{}.db", session))
+            .to_string_lossy()
+            .to_string();
+        let ended = self.session.run_with_timeout(input, db_path.clone(), self.opt.session_timeout_ms)?;
+        let verdict = if !ended {
+            Verdict::Timeout
+        } else if self.predicate.is_failure(self.session.last_session(), &db_path)? {
+            Verdict::InvariantViolation
+        } else {
+            Verdict::Pass
+        };
+        if let Some(corpus) = &self.corpus {
+            let _ = corpus.add(input, &db_path, verdict)?;
+        }
+        Ok(SessionVerdict {
+            session,
+            db_path,
+            verdict,
+        })
+    }
+}
@@ -1,17 +1,71 @@
 use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::thread;
 use std::thread::JoinHandle;
+use std::time::Duration;
 
 use scupt_net::client::{Client, OptClient, OptClientConnect};
+use scupt_net::message_sender_async::SenderAsync;
 use scupt_net::notifier::Notifier;
+use scupt_net::opt_send::OptSend;
+use scupt_util::error_type::ET;
 use scupt_util::message::{Message, MsgTrait};
 use scupt_util::node_id::NID;
 use scupt_util::res::Res;
+use scupt_util::serde_json_string::SerdeJsonString;
 use tokio::runtime;
+use tokio::sync::Mutex;
 use tokio::task::LocalSet;
+use tokio::time::{sleep, timeout};
 
-use crate::fuzzy_command::{FuzzyCmdType, FuzzyCommand};
+use crate::fuzzy_command::{DiskFault, DiskOp, FuzzyCmdType, FuzzyCommand, FuzzyDecision};
 
+/// milliseconds to wait for the reply of a request awaiting a decision, before the server is
+/// deemed unreachable
+pub const ACK_TIMEOUT_MS: u64 = 5000;
+
+/// What a client does when the fuzzy server is unreachable
+#[derive(Clone)]
+pub enum FuzzyUnreachablePolicy {
+    /// return the error at the first failed connection
+    FailFast,
+
+    /// retry connecting `retry_max` times, the wait time doubles after each failed retry
+    Retry {
+        retry_max: u64,
+        retry_wait_ms: u64,
+    },
+
+    /// bypass the fuzzy server, deliver the messages directly by the sender.
+    /// After the server was bypassed, the client no longer connects to the server
+    Bypass(Arc<dyn SenderAsync<SerdeJsonString>>),
+}
+
+impl Default for FuzzyUnreachablePolicy {
+    fn default() -> Self {
+        Self::Retry {
+            retry_max: 1,
+            retry_wait_ms: 1000,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FuzzyClientStatus {
+    /// no message was sent
+    Init,
+
+    /// the messages are sent to the fuzzy server
+    Fuzzing,
+
+    /// the fuzzy server is unreachable, the messages are dropped
+    Unreachable,
+
+    /// the fuzzy server is unreachable, the messages are delivered directly
+    Bypassed,
+}
+
+#[derive(Clone)]
 pub struct FuzzyClient {
     inner: Arc<FuzzyClientInner>,
 }
@@ -19,24 +73,62 @@ pub struct FuzzyClient {
 pub struct FuzzyClientInner {
     client: Client<FuzzyCommand>,
     _join_handler: JoinHandle<()>,
+    sequence: AtomicU64,
+    /// serialize the requests awaiting a decision, so each reply is received by its request
+    request_ack: Mutex<()>,
+    policy: FuzzyUnreachablePolicy,
+    status: std::sync::Mutex<FuzzyClientStatus>,
 }
 
 impl FuzzyClient {
     pub fn new(node_id: NID, name: String, addr: String, notifier: Notifier) -> Res<Self> {
-        let inner = FuzzyClientInner::new(node_id, name, addr, notifier)?;
+        Self::new_with_policy(node_id, name, addr, notifier, FuzzyUnreachablePolicy::default())
+    }
+
+    pub fn new_with_policy(
+        node_id: NID,
+        name: String,
+        addr: String,
+        notifier: Notifier,
+        policy: FuzzyUnreachablePolicy,
+    ) -> Res<Self> {
+        let inner = FuzzyClientInner::new(node_id, name, addr, notifier, policy)?;
         Ok(Self {
             inner: Arc::new(inner)
         })
     }
 
-    pub async fn fuzzy_rpc<M: MsgTrait + 'static>(&self, cmd_type: FuzzyCmdType, message: Message<M>) -> Res<()> {
-        self.inner.fuzzy_rpc(cmd_type, message).await?;
-        Ok(())
+    pub fn policy(&self) -> &FuzzyUnreachablePolicy {
+        &self.inner.policy
+    }
+
+    pub fn status(&self) -> FuzzyClientStatus {
+        self.inner.status()
+    }
+
+    /// Send a message to the fuzzy server.
+    /// A `MessageReqAck` request awaits and returns the decision of the server,
+    /// a `MessageReq` request returns None after the message was sent.
+    pub async fn fuzzy_rpc<M: MsgTrait + 'static>(&self, cmd_type: FuzzyCmdType, message: Message<M>) -> Res<Option<FuzzyDecision>> {
+        let opt = self.inner.fuzzy_rpc(cmd_type, message).await?;
+        Ok(opt)
+    }
+
+    /// Request the decision of the server for a disk operation of a node.
+    /// Return a `FuzzyDecision::Disk` decision, None if the server was bypassed
+    pub async fn fuzzy_disk_rpc(&self, node_id: NID, op: DiskOp) -> Res<Option<FuzzyDecision>> {
+        self.inner.fuzzy_disk_rpc(node_id, op).await
     }
 }
 
 impl FuzzyClientInner {
-    pub fn new(node_id: NID, name: String, addr: String, notifier: Notifier) -> Res<Self> {
+    pub fn new(
+        node_id: NID,
+        name: String,
+        addr: String,
+        notifier: Notifier,
+        policy: FuzzyUnreachablePolicy,
+    ) -> Res<Self> {
         let opt = OptClient {
             enable_testing: false,
         };
@@ -54,36 +146,213 @@ impl FuzzyClientInner {
         Ok(Self {
             client,
             _join_handler: join_handler,
+            sequence: AtomicU64::new(0),
+            request_ack: Mutex::new(()),
+            policy,
+            status: std::sync::Mutex::new(FuzzyClientStatus::Init),
         })
     }
 
+    fn status(&self) -> FuzzyClientStatus {
+        let _g = self.status.lock().unwrap();
+        *_g
+    }
+
+    fn set_status(&self, status: FuzzyClientStatus) {
+        let mut _g = self.status.lock().unwrap();
+        *_g = status;
+    }
+
     async fn connect(&self) -> Res<()> {
-        let mut opt = OptClientConnect::new();
-        opt.retry_max = 1;
-        opt.retry_wait_ms = 1000;
-        let r = self.client.connect(opt).await;
-        match r {
-            Ok(()) => { Ok(()) }
-            Err(e) => { Err(e) }
+        let (retry_max, mut retry_wait_ms) = match &self.policy {
+            FuzzyUnreachablePolicy::Retry { retry_max, retry_wait_ms } => {
+                (*retry_max, *retry_wait_ms)
+            }
+            _ => { (1, 1000) }
+        };
+        let mut n = 0;
+        loop {
+            let mut opt = OptClientConnect::new();
+            opt.retry_max = 1;
+            opt.retry_wait_ms = retry_wait_ms;
+            let r = self.client.connect(opt).await;
+            match r {
+                Ok(()) => { return Ok(()); }
+                Err(e) => {
+                    n += 1;
+                    if n >= retry_max {
+                        return Err(e);
+                    }
+                    sleep(Duration::from_millis(retry_wait_ms)).await;
+                    retry_wait_ms *= 2;
+                }
+            }
+        }
+    }
+
+    /// Handle the error of an unreachable server according to the policy
+    async fn unreachable(&self, error: ET, message: Message<String>) -> Res<Option<FuzzyDecision>> {
+        match &self.policy {
+            FuzzyUnreachablePolicy::Bypass(sender) => {
+                self.set_status(FuzzyClientStatus::Bypassed);
+                self.bypass(sender, message).await
+            }
+            _ => {
+                self.set_status(FuzzyClientStatus::Unreachable);
+                Err(error)
+            }
         }
     }
 
+    async fn bypass(
+        &self,
+        sender: &Arc<dyn SenderAsync<SerdeJsonString>>,
+        message: Message<String>,
+    ) -> Res<Option<FuzzyDecision>> {
+        let m = message.map(|s| { SerdeJsonString::new(s) });
+        let _ = sender.send(m, OptSend::default()).await?;
+        Ok(None)
+    }
+
 
-    async fn fuzzy_rpc<M: MsgTrait + 'static>(&self, cmd_type: FuzzyCmdType, message: Message<M>) -> Res<()> {
+    async fn fuzzy_rpc<M: MsgTrait + 'static>(&self, cmd_type: FuzzyCmdType, message: Message<M>) -> Res<Option<FuzzyDecision>> {
         let source = message.source();
         let dest = message.dest();
         let json_string = serde_json::to_string_pretty(&message).unwrap();
+        let json_message = Message::new(json_string.clone(), source, dest);
+        if let FuzzyUnreachablePolicy::Bypass(sender) = &self.policy {
+            if self.status() == FuzzyClientStatus::Bypassed {
+                return self.bypass(sender, json_message).await;
+            }
+        }
         let fuzzy_command = match cmd_type {
 
             FuzzyCmdType::MessageReq => {
                 FuzzyCommand::MessageReq(Message::new(json_string, source, dest))
             }
+            FuzzyCmdType::MessageReqAck => {
+                let id = self.sequence.fetch_add(1, Ordering::SeqCst);
+                FuzzyCommand::MessageReqAck(id, Message::new(json_string, source, dest))
+            }
+            FuzzyCmdType::MessageAck | FuzzyCmdType::ErrorReply | FuzzyCmdType::DiskReq => {
+                return Err(ET::FatalError("not a request command".to_string()));
+            }
         };
         if !self.client.is_connected().await {
-            self.connect().await?;
+            if let Err(e) = self.connect().await {
+                return self.unreachable(e, json_message).await;
+            }
+        }
+        self.set_status(FuzzyClientStatus::Fuzzing);
+        match fuzzy_command {
+            FuzzyCommand::MessageReqAck(id, _) => {
+                let _g = self.request_ack.lock().await;
+                let r = self.client.send(Message::new(fuzzy_command, source, dest)).await;
+                if let Err(e) = r {
+                    return self.unreachable(e, json_message).await;
+                }
+                let decision = match self.receive_ack(id).await {
+                    Ok(d) => { d }
+                    Err(e) => {
+                        return if e == ET::EOF {
+                            self.unreachable(Self::ack_timeout_error(id), json_message).await
+                        } else {
+                            Err(e)
+                        };
+                    }
+                };
+                Ok(Some(decision))
+            }
+            _ => {
+                let r = self.client.send(Message::new(fuzzy_command, source, dest)).await;
+                if let Err(e) = r {
+                    return self.unreachable(e, json_message).await;
+                }
+                Ok(None)
+            }
+        }
+    }
+
+    async fn fuzzy_disk_rpc(&self, node_id: NID, op: DiskOp) -> Res<Option<FuzzyDecision>> {
+        if self.status() == FuzzyClientStatus::Bypassed {
+            return Ok(None);
+        }
+        let id = self.sequence.fetch_add(1, Ordering::SeqCst);
+        let fuzzy_command = FuzzyCommand::DiskReq(id, node_id, op);
+        if !self.client.is_connected().await {
+            if let Err(e) = self.connect().await {
+                return self.disk_unreachable(e);
+            }
+        }
+        self.set_status(FuzzyClientStatus::Fuzzing);
+        let _g = self.request_ack.lock().await;
+        let r = self.client.send(Message::new(fuzzy_command, node_id, node_id)).await;
+        if let Err(e) = r {
+            return self.disk_unreachable(e);
+        }
+        let decision = match self.receive_ack(id).await {
+            Ok(d) => { d }
+            Err(e) => {
+                return if e == ET::EOF {
+                    self.disk_unreachable(Self::ack_timeout_error(id))
+                } else {
+                    Err(e)
+                };
+            }
+        };
+        match decision {
+            FuzzyDecision::Disk(_, _) => { Ok(Some(decision)) }
+            d => { Ok(Some(FuzzyDecision::Disk(d.action_id(), DiskFault::None))) }
+        }
+    }
+
+    /// Handle the error of an unreachable server for a disk request according to the policy,
+    /// a bypassed disk operation has no fault
+    fn disk_unreachable(&self, error: ET) -> Res<Option<FuzzyDecision>> {
+        match &self.policy {
+            FuzzyUnreachablePolicy::Bypass(_) => {
+                self.set_status(FuzzyClientStatus::Bypassed);
+                Ok(None)
+            }
+            _ => {
+                self.set_status(FuzzyClientStatus::Unreachable);
+                Err(error)
+            }
+        }
+    }
+
+    fn ack_timeout_error(id: u64) -> ET {
+        ET::FatalError(format!("no reply of request {} after {}ms", id, ACK_TIMEOUT_MS))
+    }
+
+    /// Receive the reply of request `id`.
+    /// Return `ET::EOF` if no reply was received in `ACK_TIMEOUT_MS` milliseconds, or the
+    /// connection was closed, the server is then unreachable
+    async fn receive_ack(&self, id: u64) -> Res<FuzzyDecision> {
+        let r = timeout(Duration::from_millis(ACK_TIMEOUT_MS), self.receive_ack_no_timeout(id)).await;
+        match r {
+            Ok(Ok(d)) => { Ok(d) }
+            Ok(Err(ET::FatalError(e))) => { Err(ET::FatalError(e)) }
+            Ok(Err(_)) | Err(_) => { Err(ET::EOF) }
         }
-        self.client.send(Message::new(fuzzy_command, source, dest)).await?;
-        Ok(())
     }
-}
 
+    async fn receive_ack_no_timeout(&self, id: u64) -> Res<FuzzyDecision> {
+        loop {
+            let m = self.client.recv().await?;
+            match m.payload() {
+                FuzzyCommand::MessageAck(_id, decision) => {
+                    if _id == id {
+                        return Ok(decision);
+                    }
+                }
+                FuzzyCommand::ErrorReply(_id, error) => {
+                    if _id == id {
+                        return Err(ET::FatalError(error));
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+}
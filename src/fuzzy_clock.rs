@@ -0,0 +1,46 @@
+use serde::{Deserialize, Serialize};
+
+/// A clock fault injected to a tested node
+#[derive(
+Clone,
+Debug,
+PartialEq,
+Eq,
+Serialize,
+Deserialize,
+)]
+pub enum ClockFault {
+    /// skew the clock by some milliseconds from the real time, forward if positive, backward if
+    /// negative, replacing the previous skew so the skew stays within `clock_max_skew_ms`
+    Skew(i64),
+
+    /// pause the clock for some milliseconds, the clock jumps forward after the pause
+    Pause(u64),
+}
+
+/// The payload of a clock fault command sent to a tested node
+#[derive(
+Serialize,
+Deserialize,
+)]
+struct ClockCommand {
+    fuzzy_clock: ClockFault,
+}
+
+impl ClockFault {
+    /// the JSON payload sent to a tested node
+    pub fn to_payload(&self) -> String {
+        let command = ClockCommand {
+            fuzzy_clock: self.clone(),
+        };
+        serde_json::to_string(&command).unwrap()
+    }
+
+    /// parse a JSON payload, return None if it is not a clock fault command
+    pub fn from_payload(json: &str) -> Option<ClockFault> {
+        match serde_json::from_str::<ClockCommand>(json) {
+            Ok(command) => { Some(command.fuzzy_clock) }
+            Err(_) => { None }
+        }
+    }
+}
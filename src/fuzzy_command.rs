@@ -1,5 +1,6 @@
 use bincode::{Decode, Encode};
 use scupt_util::message::{Message, MsgTrait};
+use scupt_util::node_id::NID;
 use serde::{Deserialize, Serialize};
 
 #[derive(
@@ -16,6 +17,99 @@ Encode,
 pub enum FuzzyCommand {
 
     MessageReq(Message<String>),
+
+    /// message request awaiting the decision of the server, with a request id
+    MessageReqAck(u64, Message<String>),
+
+    /// decision of the server for a `MessageReqAck` request
+    MessageAck(u64, FuzzyDecision),
+
+    /// error reply for a `MessageReqAck` or `DiskReq` request
+    ErrorReply(u64, String),
+
+    /// disk operation request of a node awaiting the decision of the server, with a request id
+    DiskReq(u64, NID, DiskOp),
+}
+
+/// A disk operation of a tested node
+#[derive(
+Clone,
+Hash,
+PartialEq,
+Eq,
+Debug,
+Serialize,
+Deserialize,
+Decode,
+Encode,
+)]
+pub enum DiskOp {
+    /// write some bytes
+    Write(u64),
+
+    /// flush the written bytes
+    Fsync,
+
+    /// the node crashed, with some unflushed writes
+    Crash,
+}
+
+/// A disk fault injected to a disk operation
+#[derive(
+Clone,
+Hash,
+PartialEq,
+Eq,
+Debug,
+Serialize,
+Deserialize,
+Decode,
+Encode,
+)]
+pub enum DiskFault {
+    /// no fault
+    None,
+
+    /// the fsync fails
+    FsyncError,
+
+    /// only the first bytes of the write are persisted
+    TornWrite(u64),
+
+    /// the unflushed writes are lost on crash
+    LoseUnflushed,
+}
+
+/// The decision of the server for a message, made when the message was scheduled.
+/// A message scheduled to be sent is still discarded, if a partition or a link failure
+/// disconnects its link when it is sent
+#[derive(
+Clone,
+Hash,
+PartialEq,
+Eq,
+Debug,
+Serialize,
+Deserialize,
+Decode,
+Encode,
+)]
+pub enum FuzzyDecision {
+    /// scheduled to be sent without delay, with the action id
+    Send(u64),
+
+    /// scheduled to be sent after some milliseconds, with the action id
+    SendDelayed(u64, u64),
+
+    /// buffered in the reorder window of its link, to be sent in a fuzzed order, with the
+    /// action id
+    Reorder(u64),
+
+    /// scheduled to be dropped, with the action id
+    Drop(u64),
+
+    /// disk fault of a disk operation, with the action id
+    Disk(u64, DiskFault),
 }
 
 impl MsgTrait for FuzzyCommand {}
@@ -24,10 +118,30 @@ impl FuzzyCommand {
     pub fn command_type(&self) -> FuzzyCmdType {
         match self {
             FuzzyCommand::MessageReq(_) => { FuzzyCmdType::MessageReq }
+            FuzzyCommand::MessageReqAck(_, _) => { FuzzyCmdType::MessageReqAck }
+            FuzzyCommand::MessageAck(_, _) => { FuzzyCmdType::MessageAck }
+            FuzzyCommand::ErrorReply(_, _) => { FuzzyCmdType::ErrorReply }
+            FuzzyCommand::DiskReq(_, _, _) => { FuzzyCmdType::DiskReq }
+        }
+    }
+}
+
+impl FuzzyDecision {
+    pub fn action_id(&self) -> u64 {
+        match self {
+            FuzzyDecision::Send(id) => { *id }
+            FuzzyDecision::SendDelayed(id, _) => { *id }
+            FuzzyDecision::Reorder(id) => { *id }
+            FuzzyDecision::Drop(id) => { *id }
+            FuzzyDecision::Disk(id, _) => { *id }
         }
     }
 }
 
 pub enum FuzzyCmdType {
     MessageReq,
+    MessageReqAck,
+    MessageAck,
+    ErrorReply,
+    DiskReq,
 }
@@ -0,0 +1,243 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use scupt_util::error_type::ET;
+use scupt_util::res::Res;
+use serde::{Deserialize, Serialize};
+
+const INPUT_FILE: &str = "input.bin";
+const DB_FILE: &str = "run.db";
+const VERDICT_FILE: &str = "verdict.json";
+
+/// The outcome of the run of an input
+#[derive(
+Clone,
+Copy,
+Debug,
+PartialEq,
+Eq,
+Serialize,
+Deserialize,
+)]
+pub enum Verdict {
+    /// the run ended without any failure
+    Pass,
+
+    /// an invariant was violated in the run
+    InvariantViolation,
+
+    /// the run did not end in time
+    Timeout,
+}
+
+/// An input of the corpus, with the verdict and the database of its run
+#[derive(Clone, Debug)]
+pub struct CorpusEntry {
+    /// the FNV-1a hash of the input in hex, with a suffix `-<n>` if another input has the same
+    /// hash, names the directory of the entry
+    pub id: String,
+    pub input: Vec<u8>,
+    pub verdict: Verdict,
+    /// the database recording the `action` and `delivery` tables of the run
+    pub db_path: String,
+}
+
+/// A corpus directory, each input is stored in a sub-directory named by the hash of the input:
+/// ```text
+/// <dir>/<id>/input.bin     the fuzz input
+/// <dir>/<id>/run.db        the database of its run
+/// <dir>/<id>/verdict.json  the verdict of its run
+/// ```
+/// New seeds are mutated from the interesting inputs, so a campaign builds on the earlier ones.
+pub struct FuzzyCorpus {
+    dir: PathBuf,
+}
+
+impl FuzzyCorpus {
+    /// Open the corpus directory `dir`, which is created if it does not exist
+    pub fn open(dir: String) -> Res<Self> {
+        fs::create_dir_all(&dir).map_err(|e| { ET::IOError(e.to_string()) })?;
+        Ok(Self {
+            dir: PathBuf::from(dir),
+        })
+    }
+
+    /// Store an input with the database `db_path` and the verdict of its run.
+    /// The run of an input already in the corpus is replaced, a different input with the same
+    /// hash is stored in another entry
+    pub fn add(&self, input: &[u8], db_path: &str, verdict: Verdict) -> Res<CorpusEntry> {
+        let id = self.entry_id(input)?;
+        let entry_dir = self.dir.join(&id);
+        fs::create_dir_all(&entry_dir).map_err(|e| { ET::IOError(e.to_string()) })?;
+        write_file(&entry_dir.join(INPUT_FILE), input)?;
+        let entry_db_path = entry_dir.join(DB_FILE);
+        if Path::new(db_path).exists() {
+            let _ = fs::copy(db_path, &entry_db_path).map_err(|e| { ET::IOError(e.to_string()) })?;
+        }
+        let verdict_json = serde_json::to_string_pretty(&verdict).map_err(|e| {
+            ET::FatalError(e.to_string())
+        })?;
+        write_file(&entry_dir.join(VERDICT_FILE), verdict_json.as_bytes())?;
+        Ok(CorpusEntry {
+            id,
+            input: input.to_vec(),
+            verdict,
+            db_path: entry_db_path.to_string_lossy().to_string(),
+        })
+    }
+
+    /// All the entries of the corpus, ordered by id.
+    /// A sub-directory without an input or a verdict is skipped
+    pub fn entries(&self) -> Res<Vec<CorpusEntry>> {
+        let read_dir = fs::read_dir(&self.dir).map_err(|e| { ET::IOError(e.to_string()) })?;
+        let mut vec = vec![];
+        for r in read_dir {
+            let dir_entry = r.map_err(|e| { ET::IOError(e.to_string()) })?;
+            let path = dir_entry.path();
+            if !path.is_dir() {
+                continue;
+            }
+            let input = match fs::read(path.join(INPUT_FILE)) {
+                Ok(input) => { input }
+                Err(_) => { continue; }
+            };
+            let verdict = match fs::read_to_string(path.join(VERDICT_FILE)) {
+                Ok(s) => {
+                    serde_json::from_str::<Verdict>(s.as_str()).map_err(|e| {
+                        ET::FatalError(format!("invalid verdict {}: {}", path.display(), e))
+                    })?
+                }
+                Err(_) => { continue; }
+            };
+            vec.push(CorpusEntry {
+                id: dir_entry.file_name().to_string_lossy().to_string(),
+                input,
+                verdict,
+                db_path: path.join(DB_FILE).to_string_lossy().to_string(),
+            });
+        }
+        vec.sort_by(|x, y| { x.id.cmp(&y.id) });
+        Ok(vec)
+    }
+
+    /// The entries failed by an invariant violation or a timeout
+    pub fn interesting(&self) -> Res<Vec<CorpusEntry>> {
+        let vec = self.entries()?;
+        Ok(vec.into_iter().filter(|e| { e.verdict != Verdict::Pass }).collect())
+    }
+
+    /// Mutate a new seed from an interesting entry, or from any entry if none is interesting.
+    /// The mutations are chosen by `seed`, the same corpus and seed produce the same input.
+    /// Return None if the corpus is empty
+    pub fn mutate(&self, seed: u64) -> Res<Option<Vec<u8>>> {
+        let mut entries = self.interesting()?;
+        if entries.is_empty() {
+            entries = self.entries()?;
+        }
+        if entries.is_empty() {
+            return Ok(None);
+        }
+        let mut rng = SplitMix64(seed);
+        let base = &entries[rng.below(entries.len())].input;
+        let other = &entries[rng.below(entries.len())].input;
+        Ok(Some(mutate_input(base, other, &mut rng)))
+    }
+}
+
+impl FuzzyCorpus {
+    /// The id of the entry storing `input`, an existing entry with the same input, or the first
+    /// free id with the hash of the input
+    fn entry_id(&self, input: &[u8]) -> Res<String> {
+        let hash = format!("{:016x}", fnv1a(input));
+        let mut n = 0;
+        loop {
+            let id = if n == 0 {
+                hash.clone()
+            } else {
+                format!("{}-{}", hash, n)
+            };
+            let path = self.dir.join(&id).join(INPUT_FILE);
+            if !path.exists() {
+                return Ok(id);
+            }
+            let stored = fs::read(&path).map_err(|e| { ET::IOError(e.to_string()) })?;
+            if stored == input {
+                return Ok(id);
+            }
+            n += 1;
+        }
+    }
+}
+
+/// 64-bit FNV-1a hash, stable across Rust releases, so the ids of a corpus do not change
+fn fnv1a(input: &[u8]) -> u64 {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for b in input {
+        hash ^= *b as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    hash
+}
+
+fn write_file(path: &Path, content: &[u8]) -> Res<()> {
+    fs::write(path, content).map_err(|e| { ET::IOError(e.to_string()) })
+}
+
+/// Apply a few mutations: flip a byte, insert random bytes, remove a range, or splice the tail
+/// of another input
+fn mutate_input(base: &Vec<u8>, other: &Vec<u8>, rng: &mut SplitMix64) -> Vec<u8> {
+    let mut input = base.clone();
+    let n = rng.below(4) + 1;
+    for _ in 0..n {
+        match rng.below(4) {
+            0 => {
+                if !input.is_empty() {
+                    let i = rng.below(input.len());
+                    input[i] ^= 1 << rng.below(8);
+                }
+            }
+            1 => {
+                let i = rng.below(input.len() + 1);
+                let len = rng.below(16) + 1;
+                let bytes: Vec<u8> = (0..len).map(|_| { rng.next() as u8 }).collect();
+                let _ = input.splice(i..i, bytes);
+            }
+            2 => {
+                if !input.is_empty() {
+                    let start = rng.below(input.len());
+                    let end = (start + rng.below(16) + 1).min(input.len());
+                    let _ = input.drain(start..end);
+                }
+            }
+            _ => {
+                let i = rng.below(input.len() + 1);
+                let j = rng.below(other.len() + 1);
+                input.truncate(i);
+                input.extend_from_slice(&other[j..]);
+            }
+        }
+    }
+    input
+}
+
+/// A small pseudo-random generator for the mutations
+struct SplitMix64(u64);
+
+impl SplitMix64 {
+    fn next(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e3779b97f4a7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+        z ^ (z >> 31)
+    }
+
+    /// a number in `[0, n)`, 0 if n is 0
+    fn below(&mut self, n: usize) -> usize {
+        if n == 0 {
+            0
+        } else {
+            (self.next() % n as u64) as usize
+        }
+    }
+}
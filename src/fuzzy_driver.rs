@@ -1,12 +1,11 @@
-use std::collections::HashSet;
-use std::sync::Arc;
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex};
 use std::sync::atomic::{AtomicU64, Ordering};
-use std::time::Duration;
-use arbitrary::Unstructured;
+use std::time::{Duration, Instant};
+use arbitrary::{Arbitrary, Unstructured};
 use rusqlite::Connection;
-use scc::HashSet as ConcurrentHashSet;
-use scupt_net::message_receiver_async::ReceiverAsync;
-use scupt_net::message_sender_async::SenderAsync;
+use scupt_net::message_receiver_async::ReceiverRRAsync;
+use scupt_net::message_sender_async::{SenderAsync, SenderRRAsync};
 use scupt_net::notifier::Notifier;
 use scupt_net::opt_send::OptSend;
 use scupt_net::task::spawn_local_task;
@@ -16,45 +15,117 @@ use scupt_util::node_id::NID;
 use scupt_util::res::Res;
 use scupt_util::res_of::res_sqlite;
 use scupt_util::serde_json_string::SerdeJsonString;
+use tokio::sync::Mutex as AsyncMutex;
+use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender};
 use tokio::time::sleep;
+use tracing::trace;
 
-use crate::fuzzy_command::FuzzyCommand;
+use crate::fuzzy::DELIVERY_ACTION_ID;
+use crate::fuzzy_command::{FuzzyCommand, FuzzyDecision};
 use crate::fuzzy_event::FuzzyEvent;
-use crate::event_gen::EventGen;
-use crate::fuzzy_setting::FuzzySetting;
+use crate::event_gen::FuzzyGenerator;
+use crate::fault_budget::FaultBudget;
+use crate::fuzzy_scenario::ScenarioState;
+use crate::fuzzy_setting::{DEFAULT_REORDER_FLUSH_MS, FuzzySetting};
+use crate::virtual_clock::VirtualClock;
+
+/// the bit of a deterministic action id marking a reorder release, above the event index
+const RELEASE_ID_BIT: u64 = 1 << 15;
 
 #[derive(Clone)]
 pub struct FuzzyDriver {
     path_store: String,
     notifier: Notifier,
     inner: Arc<FuzzyInner>,
-    event_gen:EventGen,
+    generator: Arc<dyn FuzzyGenerator>,
+    setting: FuzzySetting,
+    /// the events of the scenario steps triggered by received messages
+    scenario_fired: Arc<AsyncMutex<UnboundedReceiver<Vec<FuzzyEvent>>>>,
 }
 
 struct FuzzyInner {
-    dis_connect: ConcurrentHashSet<(NID, NID)>,
+    /// disconnected links, and the number of the failures disconnecting each link
+    dis_connect: Mutex<HashMap<(NID, NID), u64>>,
     atomic_sequence: AtomicU64,
+    /// sequence of the deliveries, apart from the action ids
+    delivery_sequence: AtomicU64,
     sender: Arc<dyn SenderAsync<SerdeJsonString>>,
     path: String,
+    start: Instant,
+    /// messages buffered for reordering on each link, and the generation of the buffer,
+    /// which increases after each release
+    reorder: Mutex<HashMap<(NID, NID), (u64, Vec<(u64, Message<String>)>)>>,
+    /// the simulated clock, None if the events are scheduled in real time
+    clock: Option<VirtualClock>,
+    /// the fuzz input of each link, in deterministic mode
+    streams: Mutex<HashMap<(NID, NID), Arc<AsyncMutex<LinkStream>>>>,
+    node_set: HashSet<NID>,
+    /// the live state of the crashes and partitions, bounding the faults when they execute
+    budget: FaultBudget,
+    /// the budget of each link in deterministic mode, by the link part of the action ids
+    link_budgets: Mutex<HashMap<u64, FaultBudget>>,
+    /// the live state of the hand-written scenario, None if no scenario is set
+    scenario: Option<ScenarioState>,
+    scenario_fired: UnboundedSender<Vec<FuzzyEvent>>,
+    /// tag the messages between the nodes by their action ids
+    tag_action_id: bool,
+}
+
+/// The fuzz input of a link in deterministic mode, the messages of a link draw their decisions
+/// from the stream one after another, regardless of which receiver handles them
+struct LinkStream {
+    /// index of the link, in the order of the sorted links
+    index: u64,
+    data: Vec<u8>,
+    /// offset of the bytes not consumed
+    offset: usize,
+    /// number of the messages of the link
+    sequence: u64,
 }
 
 impl FuzzyDriver {
     pub fn new(
         path: String,
         notifier: Notifier,
+        generator: Arc<dyn FuzzyGenerator>,
+        setting: FuzzySetting,
         node_set: HashSet<NID>,
-        setting:FuzzySetting,
         sender: Arc<dyn SenderAsync<SerdeJsonString>> ) -> Self {
+        let clock = if setting.virtual_time_tick_ms > 0 {
+            Some(VirtualClock::new())
+        } else {
+            None
+        };
+        let scenario = setting.scenario.as_ref().map(|s| { ScenarioState::new(s) });
+        let nodes = node_set.iter().cloned().collect();
+        let (scenario_fired, scenario_receiver) = unbounded_channel();
         Self {
             path_store: path.clone(),
             notifier,
             inner: Arc::new(FuzzyInner {
                 dis_connect: Default::default(),
                 atomic_sequence: AtomicU64::new(0),
+                delivery_sequence: AtomicU64::new(0),
                 sender,
                 path,
+                start: Instant::now(),
+                reorder: Default::default(),
+                clock,
+                streams: Default::default(),
+                node_set,
+                budget: FaultBudget::new(
+                    nodes,
+                    setting.fault_max_crashed,
+                    setting.fault_max_minority,
+                    setting.fault_max_total),
+                link_budgets: Default::default(),
+                scenario,
+                scenario_fired,
+                tag_action_id: setting.tag_action_id,
             }),
-            event_gen: EventGen::new(node_set.iter().cloned().collect(), setting),
+            generator,
+            setting,
+            scenario_fired: Arc::new(AsyncMutex::new(scenario_receiver)),
         }
     }
 
@@ -70,7 +141,8 @@ impl FuzzyDriver {
         let _r = trans.execute(
             r#"create table delivery (
                     id interger primary key,
-                    action_id integer not null
+                    action_id integer not null,
+                    time_ms integer not null
                 );"#, ());
         res_sqlite(_r)?;
         trans.commit().unwrap();
@@ -78,36 +150,480 @@ impl FuzzyDriver {
     }
 
 
+    pub fn is_deterministic(&self) -> bool {
+        self.setting.deterministic
+    }
+
+    /// Is the background fault timeline enabled
+    pub fn is_fault_scheduled(&self) -> bool {
+        self.setting.fault_interval_max_ms > 0
+    }
+
+    /// Split the fuzz input across the links between the nodes, round-robin in the order of the
+    /// sorted links, for deterministic mode.
+    /// The link of a node to itself carries the disk requests of the node.
+    /// The session ends when the stream of any link was exhausted, so the same input ends the
+    /// session after the same request of the same link, the input left on the other links is
+    /// not used. A link which carries no request, e.g. the link of a node without disk requests,
+    /// would never be exhausted, so waiting for all the links could never end the session.
+    pub fn deterministic_setup(&self, data: Vec<u8>) {
+        let mut nodes: Vec<NID> = self.inner.node_set.iter().cloned().collect();
+        nodes.sort();
+        let mut links = vec![];
+        for i in nodes.iter() {
+            for j in nodes.iter() {
+                links.push((*i, *j));
+            }
+        }
+        if links.is_empty() {
+            return;
+        }
+        let mut link_data = vec![vec![]; links.len()];
+        for (i, d) in data.iter().copied().enumerate() {
+            link_data[i % links.len()].push(d);
+        }
+        let mut streams = self.inner.streams.lock().unwrap();
+        for (index, (link, data)) in links.into_iter().zip(link_data.into_iter()).enumerate() {
+            let stream = LinkStream {
+                index: index as u64,
+                data,
+                offset: 0,
+                sequence: 0,
+            };
+            let _ = streams.insert(link, Arc::new(AsyncMutex::new(stream)));
+        }
+    }
+
     pub async fn message_loop(
         &self,
-        receiver: Arc<dyn ReceiverAsync<FuzzyCommand>>,
+        receiver: Arc<dyn ReceiverRRAsync<FuzzyCommand>>,
         data: Vec<u8>,
     ) -> Res<()> {
         let mut u = Unstructured::new(data.as_slice());
         loop {
-            let msg = receiver.receive().await?;
-            self.incoming_command(msg.payload(), &mut u).await?;
+            let (msg, responder) = receiver.receive().await?;
+            self.incoming_command(msg.payload(), responder, &mut u).await?;
+            self.advance_virtual_time().await?;
+        }
+    }
+
+    /// Advance the virtual time by a tick, and execute the events due
+    async fn advance_virtual_time(&self) -> Res<()> {
+        if let Some(clock) = &self.inner.clock {
+            let due = clock.advance(self.setting.virtual_time_tick_ms);
+            for (id, event) in due {
+                self.inner.schedule(id, event).await?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Advance the virtual time to the next scheduled event when no message arrives, so the
+    /// events scheduled after the last message, such as restarts and partition recoveries,
+    /// are executed.
+    /// The advance is triggered by real time, so it is only enabled by `virtual_time_idle_ms`,
+    /// the schedule is then no longer reproducible for the same input
+    pub async fn virtual_time_loop(&self) -> Res<()> {
+        let clock = match &self.inner.clock {
+            Some(c) => { c }
+            None => { return Ok(()); }
+        };
+        let idle_ms = self.setting.virtual_time_idle_ms;
+        if idle_ms == 0 {
+            return Ok(());
         }
+        loop {
+            sleep(Duration::from_millis(idle_ms)).await;
+            let due = clock.advance_if_idle(idle_ms);
+            for (id, event) in due {
+                self.inner.schedule(id, event).await?;
+            }
+        }
+    }
+
+    /// Inject the faults of the background fault timeline, drawn from the fuzz input `data`,
+    /// regardless of the messages of the tested nodes.
+    /// Each step of the timeline waits some milliseconds after the previous one, then crashes,
+    /// restarts or partitions the nodes. The loop ends when the fuzz input was exhausted.
+    pub async fn fault_loop(&self, data: Vec<u8>) -> Res<()> {
+        let mut u = Unstructured::new(data.as_slice());
+        let mut at_ms = 0;
+        loop {
+            let mut vec = vec![];
+            let ms = match self.generator.gen_fault(&mut u, &mut vec) {
+                Some(ms) => { ms }
+                None => { return Ok(()); }
+            };
+            match &self.inner.clock {
+                Some(clock) => {
+                    // the whole timeline is pushed to the virtual clock, relative to its start
+                    at_ms += ms;
+                    for event in vec {
+                        let id = self.inner.gen_id();
+                        self.store_event_message(id, event.clone());
+                        clock.schedule(at_ms, id, event);
+                    }
+                }
+                None => {
+                    sleep(Duration::from_millis(ms)).await;
+                    for event in vec {
+                        let id = self.inner.gen_id();
+                        self.fuzzy_event_for_message(id, event).await?;
+                    }
+                }
+            }
+        }
+    }
+
+    /// Is a hand-written scenario set
+    pub fn has_scenario(&self) -> bool {
+        self.inner.scenario.is_some()
+    }
+
+    /// Inject the faults of the scenario steps triggered by time, each step some milliseconds
+    /// after the loop started
+    pub async fn scenario_loop(&self) -> Res<()> {
+        let scenario = match &self.inner.scenario {
+            Some(s) => { s }
+            None => { return Ok(()); }
+        };
+        let start = Instant::now();
+        for (at_ms, events) in scenario.at().iter() {
+            match &self.inner.clock {
+                Some(clock) => {
+                    for event in events.iter() {
+                        let id = self.inner.gen_id();
+                        self.store_event_message(id, event.clone());
+                        clock.schedule(*at_ms, id, event.clone());
+                    }
+                }
+                None => {
+                    let elapsed = start.elapsed().as_millis() as u64;
+                    if *at_ms > elapsed {
+                        sleep(Duration::from_millis(*at_ms - elapsed)).await;
+                    }
+                    for event in events.iter() {
+                        let id = self.inner.gen_id();
+                        self.fuzzy_event_for_message(id, event.clone()).await?;
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Inject the faults of the scenario steps triggered by the messages received by the nodes
+    pub async fn scenario_trigger_loop(&self) -> Res<()> {
+        let mut receiver = self.scenario_fired.lock().await;
+        while let Some(events) = receiver.recv().await {
+            for event in events {
+                let id = self.inner.gen_id();
+                self.fuzzy_event_for_message(id, event).await?;
+            }
+        }
+        Ok(())
     }
 
-    pub async fn incoming_command(&self, command: FuzzyCommand, unstructured: &mut Unstructured<'_>) -> Res<()> {
+    /// Receive and drop the commands of the tested nodes, the requests awaiting a decision are
+    /// replied an error for `reason`.
+    /// Used in replay mode, in which the recorded messages are sent instead of the live ones, and
+    /// after the fuzz input was exhausted, so the nodes do not wait for a reply
+    pub async fn discard_loop(
+        &self,
+        receiver: Arc<dyn ReceiverRRAsync<FuzzyCommand>>,
+        reason: &str,
+    ) -> Res<()> {
+        loop {
+            let (msg, responder) = receiver.receive().await?;
+            let (req_id, link) = match msg.payload() {
+                FuzzyCommand::MessageReqAck(req_id, m) => { (req_id, (m.source(), m.dest())) }
+                FuzzyCommand::DiskReq(req_id, node_id, _) => { (req_id, (node_id, node_id)) }
+                _ => { continue; }
+            };
+            let reply = FuzzyCommand::ErrorReply(req_id, reason.to_string());
+            responder.send(Message::new(reply, link.1, link.0), OptSend::default()).await?;
+        }
+    }
+
+    /// Replay a recorded run.
+    /// Every delivery of the recorded database is sent to the tested nodes again, in the
+    /// recorded order and with the recorded intervals.
+    /// The deliveries already reflect the delays, crashes, restarts and partitions of the
+    /// recorded run, a message discarded by a partition has no delivery.
+    pub async fn replay(&self) -> Res<()> {
+        let deliveries = self.load_delivery()?;
+        let first_ms = match deliveries.first() {
+            Some((_, ms, _)) => { *ms }
+            None => { return Ok(()); }
+        };
+        let start = Instant::now();
+        for (action_id, ms, message) in deliveries {
+            let offset = ms.saturating_sub(first_ms);
+            let elapsed = start.elapsed().as_millis() as u64;
+            if offset > elapsed {
+                sleep(Duration::from_millis(offset - elapsed)).await;
+            }
+            trace!("replay action {}", action_id);
+            self.inner.send_to_node(action_id, message).await?;
+        }
+        Ok(())
+    }
+
+    /// Load the recorded deliveries, ordered by delivery id.
+    /// Return tuples of (action id, delivery time in milliseconds, message)
+    fn load_delivery(&self) -> Res<Vec<(u64, u64, Message<String>)>> {
+        let conn = res_sqlite(Connection::open(self.path_store.clone()))?;
+        let mut stmt = res_sqlite(conn.prepare(
+            r#"select delivery.action_id, delivery.time_ms, action.event
+                   from delivery join action on delivery.action_id = action.id
+                   order by delivery.id"#))?;
+        let rows = res_sqlite(stmt.query_map([], |row| {
+            Ok((row.get::<_, u64>(0)?, row.get::<_, u64>(1)?, row.get::<_, String>(2)?))
+        }))?;
+        let mut vec = vec![];
+        for r in rows {
+            let (action_id, ms, event_s) = res_sqlite(r)?;
+            let event: FuzzyEvent = serde_json::from_str(event_s.as_str()).map_err(|e| {
+                ET::FatalError(format!("invalid recorded action {}: {}", action_id, e))
+            })?;
+            if let Some(m) = event.message() {
+                vec.push((action_id, ms, m.clone()));
+            }
+        }
+        Ok(vec)
+    }
+
+    /// Send the initial state messages to the tested nodes, one after another.
+    /// Each message is recorded as an `Initialize` action and its delivery.
+    pub async fn initialize(&self, messages: Vec<Message<SerdeJsonString>>) -> Res<()> {
+        for m in messages {
+            let id = self.inner.gen_id();
+            let event = FuzzyEvent::Initialize(m.map(|s| { s.to_string() }));
+            self.store_event_message(id, event.clone());
+            self.inner.schedule(id, event).await?;
+        }
+        Ok(())
+    }
+
+    pub async fn incoming_command(
+        &self,
+        command: FuzzyCommand,
+        responder: Arc<dyn SenderRRAsync<FuzzyCommand>>,
+        unstructured: &mut Unstructured<'_>,
+    ) -> Res<()> {
         match command {
             FuzzyCommand::MessageReq(m) => {
-                let mut vec = vec![];
-                let cont = self.event_gen.fuzz_message(&m, unstructured, &mut vec);
-
-                for event in vec {
-                    let id = self.inner.gen_id();
-                    self.fuzzy_event_for_message(id, event).await?;
+                let link = (m.source(), m.dest());
+                let (_, cont) = self.fuzzy_request(link, FuzzyCommand::MessageReq(m), unstructured).await?;
+                if !cont {
+                    return Err(ET::EOF);
+                }
+            }
+            FuzzyCommand::MessageReqAck(req_id, m) => {
+                let link = (m.source(), m.dest());
+                let (decision, cont) = self.fuzzy_request(link, FuzzyCommand::MessageReq(m), unstructured).await?;
+                self.reply(req_id, link, decision, responder).await?;
+                if !cont {
+                    return Err(ET::EOF);
                 }
+            }
+            FuzzyCommand::DiskReq(req_id, node_id, op) => {
+                let link = (node_id, node_id);
+                let (decision, cont) = self.fuzzy_request(
+                    link, FuzzyCommand::DiskReq(req_id, node_id, op), unstructured).await?;
+                self.reply(req_id, link, decision, responder).await?;
                 if !cont {
                     return Err(ET::EOF);
                 }
             }
+            FuzzyCommand::MessageAck(_, _) | FuzzyCommand::ErrorReply(_, _) => {}
         }
         Ok(())
     }
 
+    async fn reply(
+        &self,
+        req_id: u64,
+        link: (NID, NID),
+        decision: Option<FuzzyDecision>,
+        responder: Arc<dyn SenderRRAsync<FuzzyCommand>>,
+    ) -> Res<()> {
+        let reply = match decision {
+            Some(d) => { FuzzyCommand::MessageAck(req_id, d) }
+            None => {
+                FuzzyCommand::ErrorReply(req_id, "no decision was made for the request".to_string())
+            }
+        };
+        let (source, dest) = link;
+        responder.send(Message::new(reply, dest, source), OptSend::default()).await?;
+        Ok(())
+    }
+
+    /// Generate and schedule the events of a request on a link, a message request is on the
+    /// link from its source to its dest, a disk request is on the link of the node to itself.
+    /// Return the decision for the request, and false if the fuzz input was exhausted, an empty
+    /// input is exhausted even if the generator did not fail, as `arbitrary` pads it with zeros
+    async fn fuzzy_request(
+        &self,
+        link: (NID, NID),
+        command: FuzzyCommand,
+        unstructured: &mut Unstructured<'_>,
+    ) -> Res<(Option<FuzzyDecision>, bool)> {
+        if self.setting.deterministic {
+            return self.fuzzy_request_deterministic(link, command).await;
+        }
+        let mut vec = vec![];
+        let cont = self.generator.gen(command, unstructured, &mut vec);
+        let ids = vec.iter().map(|_| { self.inner.gen_id() }).collect();
+        let decision = self.fuzzy_events(ids, vec, unstructured).await?;
+        Ok((decision, cont && !unstructured.is_empty()))
+    }
+
+    /// Generate and schedule the events of a request in deterministic mode.
+    /// The decisions are drawn from the fuzz input of the request link, and the action id is
+    /// derived from the link index, the sequence of the request on the link and the index of
+    /// the event, so the same input always produces the same schedule.
+    /// The faults of a link are admitted by the budget of the link, regardless of the faults of
+    /// the other links, and a reorder release is identified by the request releasing it.
+    /// Return false when the stream of the link was exhausted, see `deterministic_setup`.
+    async fn fuzzy_request_deterministic(
+        &self,
+        link: (NID, NID),
+        command: FuzzyCommand,
+    ) -> Res<(Option<FuzzyDecision>, bool)> {
+        let opt = {
+            let streams = self.inner.streams.lock().unwrap();
+            streams.get(&link).cloned()
+        };
+        let stream = match opt {
+            Some(s) => { s }
+            None => {
+                // a request of a link not between the tested nodes is not fuzzed
+                let mut u = Unstructured::new(&[]);
+                let mut vec = vec![];
+                let _ = self.generator.gen(command, &mut u, &mut vec);
+                let ids = vec.iter().map(|_| { self.inner.gen_id() }).collect();
+                let decision = self.fuzzy_events(ids, vec, &mut u).await?;
+                return Ok((decision, true));
+            }
+        };
+        let mut s = stream.lock().await;
+        let (index, sequence, offset) = (s.index, s.sequence, s.offset);
+        let (decision, cont, consumed) = {
+            let mut u = Unstructured::new(&s.data[offset..]);
+            let len = u.len();
+            let mut vec = vec![];
+            let cont = self.generator.gen(command, &mut u, &mut vec);
+            let ids = (0..vec.len() as u64).map(|i| {
+                ((index + 1) << 48) | (sequence << 16) | i
+            }).collect();
+            let decision = self.fuzzy_events(ids, vec, &mut u).await?;
+            (decision, cont && !u.is_empty(), len - u.len())
+        };
+        s.offset += consumed;
+        s.sequence += 1;
+        Ok((decision, cont))
+    }
+
+    /// Record and schedule the generated events, with their action ids.
+    /// Return the decision of the first delay or lost event
+    async fn fuzzy_events(
+        &self,
+        ids: Vec<u64>,
+        events: Vec<FuzzyEvent>,
+        unstructured: &mut Unstructured<'_>,
+    ) -> Res<Option<FuzzyDecision>> {
+        let mut decision = None;
+        for (id, event) in ids.into_iter().zip(events.into_iter()) {
+            if decision.is_none() {
+                decision = Self::decision(id, &event);
+            }
+            match event {
+                FuzzyEvent::ReorderBuffer(window, flush_ms, m) => {
+                    self.store_event_message(id, FuzzyEvent::ReorderBuffer(window, flush_ms, m.clone()));
+                    self.reorder(id, window, flush_ms, m, unstructured)?;
+                }
+                e => {
+                    self.fuzzy_event_for_message(id, e).await?;
+                }
+            }
+        }
+        Ok(decision)
+    }
+
+    /// Buffer a message in the reorder window of its link.
+    /// When the window is filled, the buffered messages are released in an order chosen from
+    /// the fuzz input.
+    fn reorder(
+        &self,
+        id: u64,
+        window: u64,
+        flush_ms: u64,
+        message: Message<String>,
+        unstructured: &mut Unstructured<'_>,
+    ) -> Res<()> {
+        let link = (message.source(), message.dest());
+        let (started, filled) = self.inner.reorder_push(link, id, window, message);
+        if let Some(mut buffered) = filled {
+            for i in (1..buffered.len()).rev() {
+                let j = u32::arbitrary(unstructured).unwrap_or(0) as usize % (i + 1);
+                buffered.swap(i, j);
+            }
+            self.reorder_release(id, buffered)?;
+        } else if let Some(generation) = started {
+            let flush_ms = if flush_ms == 0 {
+                DEFAULT_REORDER_FLUSH_MS
+            } else {
+                flush_ms
+            };
+            let flush = FuzzyEvent::ReorderFlush(link.0, link.1, generation);
+            match &self.inner.clock {
+                Some(clock) => {
+                    clock.schedule(flush_ms, id, flush);
+                }
+                None => {
+                    let inner = self.inner.clone();
+                    let _ = spawn_local_task(self.notifier.clone(), "", async move {
+                        sleep(Duration::from_millis(flush_ms)).await;
+                        inner.schedule(id, flush).await?;
+                        Ok::<(), ET>(())
+                    })?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Record the release order, and send the released messages one after another,
+    /// `id` is the action id of the message filling the window
+    fn reorder_release(&self, id: u64, buffered: Vec<(u64, Message<String>)>) -> Res<()> {
+        self.inner.store_reorder_release(id, &buffered);
+        let inner = self.inner.clone();
+        let _ = spawn_local_task(self.notifier.clone(), "", async move {
+            for (id, m) in buffered {
+                inner.send(id, m).await?;
+            }
+            Ok::<(), ET>(())
+        })?;
+        Ok(())
+    }
+
+    /// The scheduled decision of the first delay, reorder or lost event of a message
+    fn decision(id: u64, event: &FuzzyEvent) -> Option<FuzzyDecision> {
+        match event {
+            FuzzyEvent::Delay(0, _) | FuzzyEvent::Mutate(0, _) => {
+                Some(FuzzyDecision::Send(id))
+            }
+            FuzzyEvent::Delay(ms, _) | FuzzyEvent::Mutate(ms, _) => {
+                Some(FuzzyDecision::SendDelayed(id, *ms))
+            }
+            FuzzyEvent::ReorderBuffer(_, _, _) => { Some(FuzzyDecision::Reorder(id)) }
+            FuzzyEvent::Lost(_) => { Some(FuzzyDecision::Drop(id)) }
+            FuzzyEvent::Disk(_, _, fault) => { Some(FuzzyDecision::Disk(id, fault.clone())) }
+            _ => { None }
+        }
+    }
+
     fn store_event_message(&self, id: u64, event: FuzzyEvent) {
         let mut conn = Connection::open(self.path_store.clone()).unwrap();
         let transaction = conn.transaction().unwrap();
@@ -125,6 +641,11 @@ impl FuzzyDriver {
     }
 
     async fn schedule_fuzzy_event(&self, id: u64, event: FuzzyEvent) -> Res<()> {
+        if self.inner.clock.is_some() {
+            // the events are pushed to the virtual clock in the order of generation
+            self.inner.schedule(id, event).await?;
+            return Ok(());
+        }
         let inner = self.inner.clone();
         let _ = spawn_local_task(self.notifier.clone(), "", async move {
             inner.schedule(id, event).await?;
@@ -136,8 +657,23 @@ impl FuzzyDriver {
 
 impl FuzzyInner {
     async fn schedule(&self, id: u64, event: FuzzyEvent) -> Res<()> {
+        if let Some(clock) = &self.clock {
+            if let FuzzyEvent::Duplicate(vec, message) = event {
+                let mut ms = 0;
+                for d in vec {
+                    ms += d;
+                    clock.schedule(ms, id, FuzzyEvent::Delay(0, message.clone()));
+                }
+                return Ok(());
+            }
+            let ms = event.delay_ms();
+            if ms > 0 {
+                clock.schedule(ms, id, event.without_delay());
+                return Ok(());
+            }
+        }
         match event {
-            FuzzyEvent::Delay(ms, message) => {
+            FuzzyEvent::Delay(ms, message) | FuzzyEvent::Mutate(ms, message) => {
                 if ms > 0 {
                     sleep(Duration::from_millis(ms)).await;
                 }
@@ -149,20 +685,65 @@ impl FuzzyInner {
                     self.send(id, message.clone()).await?;
                 }
             }
-            FuzzyEvent::Lost => {}
+            FuzzyEvent::Lost(message) => {
+                trace!("action {}, message from {} to {} lost", id, message.source(), message.dest());
+            }
             FuzzyEvent::Restart(ms, message) => {
                 sleep(Duration::from_millis(ms)).await;
-                self.send(id, message).await?;
+                if self.admit(id, |b| { b.admit_restart(message.dest()) }) {
+                    self.send(id, message).await?;
+                } else {
+                    trace!("action {}, restart of node {} discarded with its crash", id, message.dest());
+                }
             }
             FuzzyEvent::Crash(message) => {
+                if self.admit(id, |b| { b.admit_crash(message.dest()) }) {
+                    self.send(id, message).await?;
+                } else {
+                    trace!("action {}, crash of node {} out of the fault budget", id, message.dest());
+                }
+            }
+            FuzzyEvent::ClockFault(message) => {
+                self.send(id, message).await?;
+            }
+            FuzzyEvent::Disk(node_id, op, fault) => {
+                trace!("action {}, disk operation {:?} of node {}, fault {:?}", id, op, node_id, fault);
+            }
+            FuzzyEvent::Initialize(message) => {
+                self.send(id, message).await?;
+            }
+            FuzzyEvent::ReorderBuffer(_, _, message) => {
                 self.send(id, message).await?;
             }
+            FuzzyEvent::ReorderRelease(_) => {}
+            FuzzyEvent::ReorderFlush(source, dest, generation) => {
+                if let Some(buffered) = self.reorder_take((source, dest), generation) {
+                    self.store_reorder_release(id, &buffered);
+                    for (id, m) in buffered {
+                        self.send(id, m).await?;
+                    }
+                }
+            }
             FuzzyEvent::PartitionStart(ids1, ids2) => {
-                self.partition_start(ids1, ids2);
+                self.fault_start(id, Self::partition_links(&ids1, &ids2));
             }
             FuzzyEvent::PartitionRecovery(ms, ids1, ids2) => {
                 sleep(Duration::from_millis(ms)).await;
-                self.partition_end(ids1, ids2);
+                self.fault_end(id, Self::partition_links(&ids1, &ids2));
+            }
+            FuzzyEvent::OneWayPartitionStart(ids1, ids2) => {
+                self.fault_start(id, Self::links(&ids1, &ids2));
+            }
+            FuzzyEvent::OneWayPartitionRecovery(ms, ids1, ids2) => {
+                sleep(Duration::from_millis(ms)).await;
+                self.fault_end(id, Self::links(&ids1, &ids2));
+            }
+            FuzzyEvent::LinkFailureStart(links) => {
+                self.fault_start(id, links);
+            }
+            FuzzyEvent::LinkFailureRecovery(ms, links) => {
+                sleep(Duration::from_millis(ms)).await;
+                self.fault_end(id, links);
             }
         }
         Ok(())
@@ -172,9 +753,32 @@ impl FuzzyInner {
         if !self.can_connect(message.source(), message.dest()) {
             return Ok(());
         }
+        let (source, dest) = (message.source(), message.dest());
         self.store_message_delivery(id);
+        self.send_to_node(id, message).await?;
+        if let Some(scenario) = &self.scenario {
+            // only a message from another node counts, not a fault command
+            if source != dest {
+                let events = scenario.received(dest);
+                if !events.is_empty() {
+                    let _ = self.scenario_fired.send(events);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Send a message to its dest node.
+    /// If tagging is enabled, a message between two nodes is tagged by the action id delivering
+    /// it, so the node can tell which action caused what it received
+    async fn send_to_node(&self, id: u64, message: Message<String>) -> Res<()> {
+        let tag = self.tag_action_id && message.source() != message.dest();
         let m = message.map(|s| {
-            SerdeJsonString::new(s)
+            if tag {
+                SerdeJsonString::new(tag_action_id(s, id))
+            } else {
+                SerdeJsonString::new(s)
+            }
         });
         let _ = self.sender.send(m, OptSend::default()).await?;
         Ok(())
@@ -187,34 +791,170 @@ impl FuzzyInner {
 
     fn store_message_delivery(&self, action_id: u64) {
         let mut conn = Connection::open(self.path.clone()).unwrap();
-        let id = self.gen_id();
+        let id = self.delivery_sequence.fetch_add(1, Ordering::SeqCst);
+        let ms = match &self.clock {
+            Some(clock) => { clock.now_ms() }
+            None => { self.start.elapsed().as_millis() as u64 }
+        };
         let transaction = conn.transaction().unwrap();
         let _ = transaction.execute(
-            r#"insert into delivery(id, action_id)
-                   values(?1, ?2)"#, (&id, &action_id)).unwrap();
+            r#"insert into delivery(id, action_id, time_ms)
+                   values(?1, ?2, ?3)"#, (&id, &action_id, &ms)).unwrap();
 
         transaction.commit().unwrap();
     }
 
+    /// Push a message to the reorder buffer of a link.
+    /// Return the generation of the buffer if the message is the first one buffered,
+    /// and the buffered messages if the window was filled
+    fn reorder_push(
+        &self,
+        link: (NID, NID),
+        id: u64,
+        window: u64,
+        message: Message<String>,
+    ) -> (Option<u64>, Option<Vec<(u64, Message<String>)>>) {
+        let mut reorder = self.reorder.lock().unwrap();
+        let (generation, buffered) = reorder.entry(link).or_insert((0, vec![]));
+        let started = if buffered.is_empty() {
+            Some(*generation)
+        } else {
+            None
+        };
+        buffered.push((id, message));
+        if buffered.len() as u64 >= window {
+            *generation += 1;
+            (None, Some(std::mem::take(buffered)))
+        } else {
+            (started, None)
+        }
+    }
+
+    /// Record the release order of the buffered messages of a link, the release triggered by
+    /// the action `trigger`.
+    /// In deterministic mode, the release id is the id of the trigger with `RELEASE_ID_BIT` set,
+    /// which is never set in the event index of a request
+    fn store_reorder_release(&self, trigger: u64, buffered: &Vec<(u64, Message<String>)>) {
+        let id = if trigger >> 48 > 0 {
+            trigger | RELEASE_ID_BIT
+        } else {
+            self.gen_id()
+        };
+        let order = buffered.iter().map(|(i, _)| { *i }).collect();
+        let event_s = serde_json::to_string_pretty(&FuzzyEvent::ReorderRelease(order)).unwrap();
+        let mut conn = Connection::open(self.path.clone()).unwrap();
+        let transaction = conn.transaction().unwrap();
+        let _ = transaction.execute("\
+                            insert into action(id,  event) \
+                            values(?1, ?2)", (&id, &event_s)).unwrap();
+        transaction.commit().unwrap();
+    }
+
+    /// Take the buffered messages of a link, if the buffer was not released since `generation`
+    fn reorder_take(&self, link: (NID, NID), generation: u64) -> Option<Vec<(u64, Message<String>)>> {
+        let mut reorder = self.reorder.lock().unwrap();
+        match reorder.get_mut(&link) {
+            Some((g, buffered)) => {
+                if *g != generation || buffered.is_empty() {
+                    return None;
+                }
+                *g += 1;
+                Some(std::mem::take(buffered))
+            }
+            None => { None }
+        }
+    }
+
+    /// Admit a fault of the action `id` by its budget.
+    /// A deterministic action id carries the index of its link, the link then has its own
+    /// budget, so the admission does not depend on the faults of the other links
+    fn admit<F: FnOnce(&FaultBudget) -> bool>(&self, id: u64, f: F) -> bool {
+        let link = id >> 48;
+        if link == 0 {
+            return f(&self.budget);
+        }
+        let mut budgets = self.link_budgets.lock().unwrap();
+        let budget = budgets.entry(link).or_insert_with(|| { self.budget.with_same_limits() });
+        f(budget)
+    }
+
     fn can_connect(&self, id1: NID, id2: NID) -> bool {
-        !self.dis_connect.contains(&(id1, id2))
+        let dis_connect = self.dis_connect.lock().unwrap();
+        !dis_connect.contains_key(&(id1, id2))
+    }
+
+    /// Disconnect the links of a partition or a link failure, if the fault budget admits it
+    fn fault_start(&self, id: u64, links: Vec<(NID, NID)>) {
+        if self.admit(id, |b| { b.admit_failure(&links) }) {
+            self.link_failure_start(links);
+        } else {
+            trace!("action {}, failure of links {:?} out of the fault budget", id, links);
+        }
+    }
+
+    /// Recover the links of a partition or a link failure, unless the failure was discarded
+    fn fault_end(&self, id: u64, links: Vec<(NID, NID)>) {
+        if self.admit(id, |b| { b.admit_recovery(&links) }) {
+            self.link_failure_end(links);
+        } else {
+            trace!("action {}, recovery of links {:?} discarded with its failure", id, links);
+        }
     }
 
-    fn partition_end(&self, ids1: Vec<NID>, ids2: Vec<NID>) {
-        for i in &ids1 {
-            for j in &ids2 {
-                let _ = self.dis_connect.remove(&(*i, *j));
+    /// A link is recovered after all the failures disconnecting it were recovered,
+    /// so overlapping partitions and link failures can be recovered in any order
+    fn link_failure_end(&self, links: Vec<(NID, NID)>) {
+        let mut dis_connect = self.dis_connect.lock().unwrap();
+        for link in links {
+            let remove = match dis_connect.get_mut(&link) {
+                Some(n) => {
+                    *n -= 1;
+                    *n == 0
+                }
+                None => { false }
+            };
+            if remove {
+                let _ = dis_connect.remove(&link);
             }
         }
     }
 
-    fn partition_start(&self, ids1: Vec<NID>, ids2: Vec<NID>) {
-        for i in &ids1 {
-            for j in &ids2 {
+    fn link_failure_start(&self, links: Vec<(NID, NID)>) {
+        let mut dis_connect = self.dis_connect.lock().unwrap();
+        for link in links {
+            *dis_connect.entry(link).or_insert(0) += 1;
+        }
+    }
+
+    /// the links between `ids1` and `ids2` in both directions
+    fn partition_links(ids1: &Vec<NID>, ids2: &Vec<NID>) -> Vec<(NID, NID)> {
+        let mut links = Self::links(ids1, ids2);
+        links.extend(Self::links(ids2, ids1));
+        links
+    }
+
+    /// the links from `ids1` to `ids2`
+    fn links(ids1: &Vec<NID>, ids2: &Vec<NID>) -> Vec<(NID, NID)> {
+        let mut links = vec![];
+        for i in ids1 {
+            for j in ids2 {
                 if *i != *j {
-                    let _ = self.dis_connect.insert((*i, *j));
+                    links.push((*i, *j));
                 }
             }
         }
+        links
+    }
+}
+
+/// Add the action id to the JSON object of a delivered message, a message not a JSON object is
+/// not tagged
+fn tag_action_id(json: String, id: u64) -> String {
+    match serde_json::from_str::<serde_json::Value>(json.as_str()) {
+        Ok(serde_json::Value::Object(mut map)) => {
+            let _ = map.insert(DELIVERY_ACTION_ID.to_string(), serde_json::Value::from(id));
+            serde_json::Value::Object(map).to_string()
+        }
+        _ => { json }
     }
 }
@@ -2,6 +2,8 @@ use scupt_util::message::Message;
 use scupt_util::node_id::NID;
 use serde::{Deserialize, Serialize};
 
+use crate::fuzzy_command::{DiskFault, DiskOp};
+
 
 #[derive(
 Clone,
@@ -14,7 +16,22 @@ pub enum FuzzyEvent {
     Delay(u64, Message<String>),
 
     /// message was lost
-    Lost,
+    Lost(Message<String>),
+
+    /// deliver a message with a mutated payload after some milliseconds
+    Mutate(u64, Message<String>),
+
+    /// buffer a message in the reorder window of its link, with the window size and the
+    /// milliseconds after which an unfilled window is released
+    ReorderBuffer(u64, u64, Message<String>),
+
+    /// release the buffered messages of a link, in the order of the action ids
+    ReorderRelease(Vec<u64>),
+
+    /// release the unfilled reorder window of the link from the first node to the second one,
+    /// if it was not released since the generation, scheduled on the virtual clock and never
+    /// recorded
+    ReorderFlush(NID, NID, u64),
 
     /// duplicate message
     Duplicate(Vec<u64>, Message<String>),
@@ -23,9 +40,77 @@ pub enum FuzzyEvent {
 
     Restart(u64, Message<String>),
 
+    /// disk fault decision for a disk operation of a node
+    Disk(NID, DiskOp, DiskFault),
+
+    /// clock fault command sent to a node, the payload is a `ClockFault` payload
+    ClockFault(Message<String>),
+
+    /// bidirectional partition, the two groups cannot send messages to each other
     PartitionStart(Vec<NID>, Vec<NID>),
 
     PartitionRecovery(u64, Vec<NID>, Vec<NID>),
+
+    /// one-way partition, the messages from the first group to the second group are discarded,
+    /// the messages in the opposite direction are still delivered
+    OneWayPartitionStart(Vec<NID>, Vec<NID>),
+
+    OneWayPartitionRecovery(u64, Vec<NID>, Vec<NID>),
+
+    /// disconnect a set of links, each link (i, j) discards the messages from node i to node j
+    LinkFailureStart(Vec<(NID, NID)>),
+
+    LinkFailureRecovery(u64, Vec<(NID, NID)>),
+
+    /// initial state message, sent before fuzzing starts
+    Initialize(Message<String>),
 }
 
+impl FuzzyEvent {
+    /// the message carried by this event, if the event delivers a message
+    pub fn message(&self) -> Option<&Message<String>> {
+        match self {
+            FuzzyEvent::Delay(_, m) => { Some(m) }
+            FuzzyEvent::Mutate(_, m) => { Some(m) }
+            FuzzyEvent::ReorderBuffer(_, _, m) => { Some(m) }
+            FuzzyEvent::Duplicate(_, m) => { Some(m) }
+            FuzzyEvent::Crash(m) => { Some(m) }
+            FuzzyEvent::Restart(_, m) => { Some(m) }
+            FuzzyEvent::ClockFault(m) => { Some(m) }
+            FuzzyEvent::Initialize(m) => { Some(m) }
+            _ => { None }
+        }
+    }
+
+    /// the milliseconds to wait before executing this event
+    pub fn delay_ms(&self) -> u64 {
+        match self {
+            FuzzyEvent::Delay(ms, _) => { *ms }
+            FuzzyEvent::Mutate(ms, _) => { *ms }
+            FuzzyEvent::Restart(ms, _) => { *ms }
+            FuzzyEvent::PartitionRecovery(ms, _, _) => { *ms }
+            FuzzyEvent::OneWayPartitionRecovery(ms, _, _) => { *ms }
+            FuzzyEvent::LinkFailureRecovery(ms, _) => { *ms }
+            _ => { 0 }
+        }
+    }
 
+    /// this event to be executed without waiting
+    pub fn without_delay(self) -> FuzzyEvent {
+        match self {
+            FuzzyEvent::Delay(_, m) => { FuzzyEvent::Delay(0, m) }
+            FuzzyEvent::Mutate(_, m) => { FuzzyEvent::Mutate(0, m) }
+            FuzzyEvent::Restart(_, m) => { FuzzyEvent::Restart(0, m) }
+            FuzzyEvent::PartitionRecovery(_, ids1, ids2) => {
+                FuzzyEvent::PartitionRecovery(0, ids1, ids2)
+            }
+            FuzzyEvent::OneWayPartitionRecovery(_, ids1, ids2) => {
+                FuzzyEvent::OneWayPartitionRecovery(0, ids1, ids2)
+            }
+            FuzzyEvent::LinkFailureRecovery(_, links) => {
+                FuzzyEvent::LinkFailureRecovery(0, links)
+            }
+            e => { e }
+        }
+    }
+}
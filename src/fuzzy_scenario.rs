@@ -0,0 +1,285 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use scupt_util::error_type::ET;
+use scupt_util::message::Message;
+use scupt_util::node_id::NID;
+use scupt_util::res::Res;
+use serde::{Deserialize, Serialize};
+
+use crate::fuzzy_clock::ClockFault;
+use crate::fuzzy_event::FuzzyEvent;
+
+/// A hand-written fault scenario, the faults are expressed directly instead of being drawn
+/// from the fuzz input, e.g. in JSON:
+/// ```json
+/// {
+///   "steps": [
+///     { "trigger": { "At": 200 },
+///       "fault": { "Partition": { "group1": [1, 2], "group2": [3], "duration_ms": 1000 } } },
+///     { "trigger": { "AfterReceived": { "node": 2, "count": 5 } },
+///       "fault": { "Crash": { "node": 2, "crash_payload": "...", "restart_payload": "...",
+///                             "restart_after_ms": 500 } } }
+///   ]
+/// }
+/// ```
+/// The faults are executed by the fuzzy server along with the fuzzed events, so a known bug
+/// can be locked in as a test.
+#[derive(
+Serialize,
+Deserialize,
+Clone,
+Debug,
+Default,
+)]
+pub struct FuzzyScenario {
+    pub steps: Vec<ScenarioStep>,
+}
+
+#[derive(
+Serialize,
+Deserialize,
+Clone,
+Debug,
+)]
+pub struct ScenarioStep {
+    pub trigger: ScenarioTrigger,
+    pub fault: ScenarioFault,
+}
+
+/// When a fault of a scenario is injected
+#[derive(
+Serialize,
+Deserialize,
+Clone,
+Debug,
+)]
+pub enum ScenarioTrigger {
+    /// some milliseconds after the fuzzing started
+    At(u64),
+
+    /// after the node received some messages from the other nodes
+    AfterReceived {
+        node: NID,
+        count: u64,
+    },
+}
+
+/// A fault of a scenario
+#[derive(
+Serialize,
+Deserialize,
+Clone,
+Debug,
+)]
+pub enum ScenarioFault {
+    /// crash the node by the crash payload, and restart it by the restart payload some
+    /// milliseconds later, if a restart payload is set
+    Crash {
+        node: NID,
+        crash_payload: String,
+        #[serde(default)]
+        restart_payload: Option<String>,
+        #[serde(default)]
+        restart_after_ms: u64,
+    },
+
+    /// partition the nodes of `group1` from the nodes of `group2` for some milliseconds,
+    /// only the messages from `group1` to `group2` are discarded if `one_way` is set
+    Partition {
+        group1: Vec<NID>,
+        group2: Vec<NID>,
+        duration_ms: u64,
+        #[serde(default)]
+        one_way: bool,
+    },
+
+    /// fail the links for some milliseconds
+    LinkFailure {
+        links: Vec<(NID, NID)>,
+        duration_ms: u64,
+    },
+
+    /// inject a clock fault to the node
+    Clock {
+        node: NID,
+        fault: ClockFault,
+    },
+}
+
+impl FuzzyScenario {
+    pub fn from_json(json: &str) -> Res<Self> {
+        serde_json::from_str(json).map_err(|e| {
+            ET::FatalError(format!("invalid scenario: {}", e))
+        })
+    }
+}
+
+impl ScenarioFault {
+    /// the fuzzy events executing this fault
+    pub fn events(&self) -> Vec<FuzzyEvent> {
+        match self {
+            ScenarioFault::Crash { node, crash_payload, restart_payload, restart_after_ms } => {
+                let mut vec = vec![
+                    FuzzyEvent::Crash(Message::new(crash_payload.clone(), *node, *node))
+                ];
+                if let Some(payload) = restart_payload {
+                    vec.push(FuzzyEvent::Restart(
+                        *restart_after_ms, Message::new(payload.clone(), *node, *node)));
+                }
+                vec
+            }
+            ScenarioFault::Partition { group1, group2, duration_ms, one_way } => {
+                if *one_way {
+                    vec![
+                        FuzzyEvent::OneWayPartitionStart(group1.clone(), group2.clone()),
+                        FuzzyEvent::OneWayPartitionRecovery(*duration_ms, group1.clone(), group2.clone()),
+                    ]
+                } else {
+                    vec![
+                        FuzzyEvent::PartitionStart(group1.clone(), group2.clone()),
+                        FuzzyEvent::PartitionRecovery(*duration_ms, group1.clone(), group2.clone()),
+                    ]
+                }
+            }
+            ScenarioFault::LinkFailure { links, duration_ms } => {
+                vec![
+                    FuzzyEvent::LinkFailureStart(links.clone()),
+                    FuzzyEvent::LinkFailureRecovery(*duration_ms, links.clone()),
+                ]
+            }
+            ScenarioFault::Clock { node, fault } => {
+                vec![FuzzyEvent::ClockFault(Message::new(fault.to_payload(), *node, *node))]
+            }
+        }
+    }
+}
+
+/// The live state of a scenario in a fuzzy driver
+pub(crate) struct ScenarioState {
+    /// the events of the steps triggered by time, ordered by time
+    at: Vec<(u64, Vec<FuzzyEvent>)>,
+    /// number of the messages received by each node
+    received: Mutex<HashMap<NID, u64>>,
+    /// the events of the steps triggered by received messages, not yet triggered
+    after_received: Mutex<Vec<(NID, u64, Vec<FuzzyEvent>)>>,
+}
+
+impl ScenarioState {
+    pub fn new(scenario: &FuzzyScenario) -> Self {
+        let mut at = vec![];
+        let mut after_received = vec![];
+        for step in scenario.steps.iter() {
+            match &step.trigger {
+                ScenarioTrigger::At(ms) => {
+                    at.push((*ms, step.fault.events()));
+                }
+                ScenarioTrigger::AfterReceived { node, count } => {
+                    after_received.push((*node, *count, step.fault.events()));
+                }
+            }
+        }
+        at.sort_by_key(|(ms, _)| { *ms });
+        Self {
+            at,
+            received: Default::default(),
+            after_received: Mutex::new(after_received),
+        }
+    }
+
+    /// the events of the steps triggered by time, ordered by time
+    pub fn at(&self) -> &Vec<(u64, Vec<FuzzyEvent>)> {
+        &self.at
+    }
+
+    /// The node received a message, return the events of the steps triggered
+    pub fn received(&self, node_id: NID) -> Vec<FuzzyEvent> {
+        let count = {
+            let mut received = self.received.lock().unwrap();
+            let n = received.entry(node_id).or_insert(0);
+            *n += 1;
+            *n
+        };
+        let mut after_received = self.after_received.lock().unwrap();
+        let mut events = vec![];
+        after_received.retain(|(node, n, vec)| {
+            if *node == node_id && *n <= count {
+                events.extend(vec.iter().cloned());
+                false
+            } else {
+                true
+            }
+        });
+        events
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::fuzzy_event::FuzzyEvent;
+    use crate::fuzzy_scenario::{FuzzyScenario, ScenarioFault, ScenarioState, ScenarioTrigger};
+
+    /// the example of the doc comment of `FuzzyScenario`
+    const EXAMPLE: &str = r#"
+    {
+      "steps": [
+        { "trigger": { "At": 200 },
+          "fault": { "Partition": { "group1": [1, 2], "group2": [3], "duration_ms": 1000 } } },
+        { "trigger": { "AfterReceived": { "node": 2, "count": 5 } },
+          "fault": { "Crash": { "node": 2, "crash_payload": "...", "restart_payload": "...",
+                                "restart_after_ms": 500 } } }
+      ]
+    }
+    "#;
+
+    #[test]
+    fn test_from_json() {
+        let scenario = FuzzyScenario::from_json(EXAMPLE).unwrap();
+        assert_eq!(scenario.steps.len(), 2);
+        assert!(matches!(scenario.steps[0].trigger, ScenarioTrigger::At(200)));
+        match &scenario.steps[0].fault {
+            ScenarioFault::Partition { group1, group2, duration_ms, one_way } => {
+                assert_eq!(*group1, vec![1, 2]);
+                assert_eq!(*group2, vec![3]);
+                assert_eq!(*duration_ms, 1000);
+                assert!(!*one_way);
+            }
+            f => { panic!("unexpected fault {:?}", f) }
+        }
+        assert!(matches!(scenario.steps[1].trigger, ScenarioTrigger::AfterReceived { node: 2, count: 5 }));
+        match &scenario.steps[1].fault {
+            ScenarioFault::Crash { node, restart_payload, restart_after_ms, .. } => {
+                assert_eq!(*node, 2);
+                assert_eq!(restart_payload.as_deref(), Some("..."));
+                assert_eq!(*restart_after_ms, 500);
+            }
+            f => { panic!("unexpected fault {:?}", f) }
+        }
+        assert!(FuzzyScenario::from_json("{ \"steps\": [ {} ] }").is_err());
+    }
+
+    #[test]
+    fn test_after_received() {
+        let scenario = FuzzyScenario::from_json(EXAMPLE).unwrap();
+        let state = ScenarioState::new(&scenario);
+        assert_eq!(state.at().len(), 1);
+        assert_eq!(state.at()[0].0, 200);
+
+        // the messages received by another node do not count
+        for _ in 0..10 {
+            assert!(state.received(1).is_empty());
+        }
+        for _ in 1..5 {
+            assert!(state.received(2).is_empty());
+        }
+        let events = state.received(2);
+        assert_eq!(events.len(), 2);
+        assert!(matches!(events[0], FuzzyEvent::Crash(_)));
+        assert!(matches!(events[1], FuzzyEvent::Restart(500, _)));
+
+        // the step fires only once
+        for _ in 0..10 {
+            assert!(state.received(2).is_empty());
+        }
+    }
+}
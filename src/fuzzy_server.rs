@@ -7,8 +7,7 @@ use scupt_net::es_option::{ESConnectOpt, ESServeOpt};
 use scupt_net::event_sink_async::EventSinkAsync;
 use scupt_net::io_service::{IOService, IOServiceOpt};
 use scupt_net::io_service_async::IOServiceAsync;
-use scupt_net::message_receiver_async::ReceiverAsync;
-use scupt_net::message_sender_async::SenderAsync;
+use scupt_net::message_receiver_async::ReceiverRRAsync;
 use scupt_net::notifier::Notifier;
 use scupt_net::task::spawn_local_task;
 use scupt_util::error_type::ET;
@@ -21,9 +20,11 @@ use tokio::task::LocalSet;
 use tokio::time::sleep;
 use tracing::trace;
 
+use crate::event_gen::{EventGen, FuzzyGenerator};
 use crate::fuzzy_command::FuzzyCommand;
 use crate::fuzzy_driver::FuzzyDriver;
 use crate::fuzzy_setting::FuzzySetting;
+use crate::initializer::{Initializer, InitializerPhantom};
 
 pub struct FuzzyServer {
     inner: Arc<FuzzyServerInner>,
@@ -34,10 +35,12 @@ struct FuzzyServerInner {
     server_addr: SocketAddr,
     notifier: Notifier,
     fuzzy_driver: Arc<FuzzyDriver>,
+    initializer: Arc<dyn Initializer>,
     service_message_to_nodes: Arc<dyn IOServiceAsync<SerdeJsonString>>,
     service_message_incoming: Arc<dyn IOServiceAsync<FuzzyCommand>>,
     data:Mutex<Vec<u8>>,
     notify_end_data:Notifier,
+    replay:bool,
 }
 
 
@@ -52,6 +55,39 @@ impl FuzzyServer {
         setting:FuzzySetting,
         data:Vec<u8>,
         notify_end_data:Notifier,
+    ) -> Res<Self> {
+        let generator = Arc::new(EventGen::new(peers.keys().cloned().collect(), setting.clone()));
+        Self::new_with_generator(
+            nid,
+            name,
+            path,
+            notifier,
+            server_addr,
+            peers,
+            generator,
+            Arc::new(InitializerPhantom::default()),
+            setting,
+            data,
+            notify_end_data,
+        )
+    }
+
+    /// Create a server generating the fuzzy events by a user defined `generator`, `setting`
+    /// drives the virtual time, the fault timeline and budget, and the scenario.
+    /// The messages of `initializer` are sent to the tested nodes after all nodes were connected
+    /// and before the first fuzzy message is processed.
+    pub fn new_with_generator(
+        nid: NID,
+        name: String,
+        path: String,
+        notifier: Notifier,
+        server_addr: SocketAddr,
+        peers: HashMap<NID, SocketAddr>,
+        generator:Arc<dyn FuzzyGenerator>,
+        initializer:Arc<dyn Initializer>,
+        setting:FuzzySetting,
+        data:Vec<u8>,
+        notify_end_data:Notifier,
     ) -> Res<Self> {
         let r = Self {
             inner: Arc::new(
@@ -62,9 +98,44 @@ impl FuzzyServer {
                     notifier,
                     server_addr,
                     peers,
+                    generator,
+                    initializer,
                     setting,
                     data,
-                    notify_end_data
+                    notify_end_data,
+                    false,
+                )?),
+        };
+        Ok(r)
+    }
+
+    /// Create a server replaying the run recorded in the database `path`.
+    /// The messages of the tested nodes are discarded, and the recorded deliveries are sent
+    /// instead, `notify_end_data` is notified after the last delivery was sent.
+    pub fn new_replay(
+        nid: NID,
+        name: String,
+        path: String,
+        notifier: Notifier,
+        server_addr: SocketAddr,
+        peers: HashMap<NID, SocketAddr>,
+        notify_end_data:Notifier,
+    ) -> Res<Self> {
+        let r = Self {
+            inner: Arc::new(
+                FuzzyServerInner::new(
+                    nid,
+                    name,
+                    path,
+                    notifier,
+                    server_addr,
+                    peers,
+                    Arc::new(EventGen::new(vec![], FuzzySetting::default())),
+                    Arc::new(InitializerPhantom::default()),
+                    FuzzySetting::default(),
+                    vec![],
+                    notify_end_data,
+                    true,
                 )?),
         };
         Ok(r)
@@ -83,9 +154,12 @@ impl FuzzyServerInner {
            notify: Notifier,
            server_addr: SocketAddr,
            peers: HashMap<NID, SocketAddr>,
+           generator:Arc<dyn FuzzyGenerator>,
+           initializer:Arc<dyn Initializer>,
            setting:FuzzySetting,
            data:Vec<u8>,
            notify_end_data:Notifier,
+           replay:bool,
     ) -> Res<Self> {
         let opt1 = IOServiceOpt {
             num_message_receiver: 1,
@@ -118,17 +192,22 @@ impl FuzzyServerInner {
                 FuzzyDriver::new(
                     path,
                     notify.clone(),
-                    peers.clone().keys().map(|i| { *i }).collect(),
+                    generator,
                     setting,
+                    peers.keys().cloned().collect(),
                     sender_to_node,
                 )),
+            initializer,
             service_message_to_nodes: service_to_nodes,
             service_message_incoming: server_service_incoming,
 
             data: Mutex::new(data),
             notify_end_data,
+            replay,
         };
-        inner.fuzzy_driver.create_db()?;
+        if !replay {
+            inner.fuzzy_driver.create_db()?;
+        }
         Ok(inner)
     }
 
@@ -171,9 +250,9 @@ impl FuzzyServerInner {
         let server_sink_connect_to_node = self.service_message_to_nodes.default_sink();
         let notifier1 = self.notifier.clone();
         let driver = self.fuzzy_driver.clone();
-        let receiver = self.service_message_incoming.receiver();
+        let receiver = self.service_message_incoming.receiver_rr();
         let notifier = self.notifier.clone();
-        let sender_initialize_state = self.service_message_to_nodes.new_sender("send initialize state".to_string())?;
+        let initializer = self.initializer.clone();
         let notify1 = Arc::new(Notify::new());
         let notify2 = notify1.clone();
         let data = {
@@ -183,15 +262,25 @@ impl FuzzyServerInner {
             v
         };
         let notify_end_data = self.notify_end_data.clone();
+        let replay = self.replay;
         ls.spawn_local(async move {
             let _ = spawn_local_task(notifier, "server_start", async move {
                 Self::server_serve(server_sink_message_incoming, server_address).await?;
                 Self::server_connect_to_all_tested_nodes(
-                    server_sink_connect_to_node, client_connect_to_peers,
-                    sender_initialize_state, notify1).await?;
-                Self::server_handle_recv_message(
-                    notifier1, notify_end_data,
-                    driver, receiver, notify2, data).await?;
+                    server_sink_connect_to_node, client_connect_to_peers).await?;
+                if !replay {
+                    driver.initialize(initializer.message()).await?;
+                }
+                notify1.notify_one();
+                if replay {
+                    Self::server_replay(
+                        notifier1, notify_end_data,
+                        driver, receiver, notify2).await?;
+                } else {
+                    Self::server_handle_recv_message(
+                        notifier1, notify_end_data,
+                        driver, receiver, notify2, data).await?;
+                }
                 Ok::<(), ET>(())
             });
         });
@@ -209,8 +298,6 @@ impl FuzzyServerInner {
     pub async fn server_connect_to_all_tested_nodes(
         sink: Arc<dyn EventSinkAsync<SerdeJsonString>>,
         node_address: HashMap<NID, SocketAddr>,
-        _sender: Arc<dyn SenderAsync<SerdeJsonString>>,
-        notify: Arc<Notify>,
     ) -> Res<()> {
         let mut connected = HashSet::new();
         loop {
@@ -235,8 +322,6 @@ impl FuzzyServerInner {
             }
         }
         trace!("serve player, connect to all");
-
-        notify.notify_one();
         Ok(())
     }
 
@@ -253,13 +338,53 @@ impl FuzzyServerInner {
         notifier: Notifier,
         notify_end_data:Notifier,
         fuzzy_driver: Arc<FuzzyDriver>,
-        receiver: Vec<Arc<dyn ReceiverAsync<FuzzyCommand>>>,
+        receiver: Vec<Arc<dyn ReceiverRRAsync<FuzzyCommand>>>,
         start: Arc<Notify>,
         vec:Vec<u8>,
     ) -> Res<()> {
-        let mut v = Self::splice(receiver.len(), vec);
+        // the background fault timeline draws its faults from its own part of the fuzz input
+        let (fault_data, vec) = if fuzzy_driver.is_fault_scheduled() {
+            let mut v = Self::splice(2, vec);
+            let rest = v.pop().unwrap();
+            (v.pop().unwrap(), rest)
+        } else {
+            (vec![], vec)
+        };
+        let mut v = if fuzzy_driver.is_deterministic() {
+            fuzzy_driver.deterministic_setup(vec);
+            vec![vec![]; receiver.len()]
+        } else {
+            Self::splice(receiver.len(), vec)
+        };
         start.notified().await;
 
+        let driver = fuzzy_driver.clone();
+        let _ = spawn_local_task(notifier.clone(), "virtual time", async move {
+            driver.virtual_time_loop().await?;
+            Ok::<(), ET>(())
+        })?;
+
+        if fuzzy_driver.has_scenario() {
+            let driver = fuzzy_driver.clone();
+            let _ = spawn_local_task(notifier.clone(), "scenario", async move {
+                driver.scenario_loop().await?;
+                Ok::<(), ET>(())
+            })?;
+            let driver = fuzzy_driver.clone();
+            let _ = spawn_local_task(notifier.clone(), "scenario trigger", async move {
+                driver.scenario_trigger_loop().await?;
+                Ok::<(), ET>(())
+            })?;
+        }
+
+        if fuzzy_driver.is_fault_scheduled() {
+            let driver = fuzzy_driver.clone();
+            let _ = spawn_local_task(notifier.clone(), "fault timeline", async move {
+                driver.fault_loop(fault_data).await?;
+                Ok::<(), ET>(())
+            })?;
+        }
+
         for (i, r) in receiver.iter().enumerate() {
             let mut _v = vec![];
             std::mem::swap(&mut _v, &mut v[i]);
@@ -267,12 +392,13 @@ impl FuzzyServerInner {
             let _end_notify = notify_end_data.clone();
             let driver = fuzzy_driver.clone();
             let _ = spawn_local_task(notifier.clone(), "", async move {
-                let r = driver.message_loop(_r, _v).await;
+                let r = driver.message_loop(_r.clone(), _v).await;
                 match r {
                     Ok(_) => {}
                     Err(e) => {
                         if e == ET::EOF {
                             _end_notify.notify_all();
+                            driver.discard_loop(_r, "the fuzz input was exhausted").await?;
                         }
                     }
                 }
@@ -281,4 +407,28 @@ impl FuzzyServerInner {
         }
         Ok(())
     }
+
+    async fn server_replay(
+        notifier: Notifier,
+        notify_end_data:Notifier,
+        fuzzy_driver: Arc<FuzzyDriver>,
+        receiver: Vec<Arc<dyn ReceiverRRAsync<FuzzyCommand>>>,
+        start: Arc<Notify>,
+    ) -> Res<()> {
+        start.notified().await;
+        for r in receiver.iter() {
+            let _r = r.clone();
+            let driver = fuzzy_driver.clone();
+            let _ = spawn_local_task(notifier.clone(), "", async move {
+                driver.discard_loop(_r, "replaying a recorded run").await?;
+                Ok::<(), ET>(())
+            })?;
+        }
+        let _ = spawn_local_task(notifier.clone(), "replay", async move {
+            fuzzy_driver.replay().await?;
+            notify_end_data.notify_all();
+            Ok::<(), ET>(())
+        })?;
+        Ok(())
+    }
 }
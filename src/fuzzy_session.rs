@@ -0,0 +1,211 @@
+use std::collections::HashMap;
+use std::fs;
+use std::net::SocketAddr;
+use std::path::Path;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use scupt_net::notifier::Notifier;
+use scupt_util::error_type::ET;
+use scupt_util::node_id::NID;
+use scupt_util::res::Res;
+
+use crate::event_gen::{EventGen, FuzzyGenerator};
+use crate::fuzzy_server::FuzzyServer;
+use crate::fuzzy_setting::FuzzySetting;
+use crate::initializer::{Initializer, InitializerPhantom};
+use crate::lifecycle::{ClusterLifecycle, LifecyclePhantom};
+use crate::server_config::ServerConfig;
+
+/// A fuzzy testing session restarts the tested cluster by its lifecycle hook, runs a fuzzy
+/// server for one input, and returns after the input bytes were exhausted.
+/// It can be used as the body of a coverage guided fuzz target, the fault schedule becomes
+/// the fuzzed input:
+/// ```ignore
+/// lazy_static! {
+///     static ref SESSION: FuzzySession = FuzzySession::new_with_lifecycle(
+///         config(), setting(), Arc::new(Cluster::new()));
+/// }
+///
+/// fuzz_target!(|data: &[u8]| {
+///     SESSION.run(data).unwrap();
+/// });
+/// ```
+/// The `action` table of the database `ServerConfig::db_path` records the fault schedule of
+/// the last session, and can be replayed by `FuzzyServer::new_replay` to reproduce a crash.
+pub struct FuzzySession {
+    config: ServerConfig,
+    generator: Arc<dyn FuzzyGenerator>,
+    initializer: Arc<dyn Initializer>,
+    lifecycle: Arc<dyn ClusterLifecycle>,
+    setting: FuzzySetting,
+    /// number of the sessions run
+    sequence: AtomicU64,
+}
+
+impl FuzzySession {
+    pub fn new(
+        config: ServerConfig,
+        generator: Arc<dyn FuzzyGenerator>,
+        initializer: Arc<dyn Initializer>,
+        lifecycle: Arc<dyn ClusterLifecycle>,
+        setting: FuzzySetting,
+    ) -> Self {
+        Self {
+            config,
+            generator,
+            initializer,
+            lifecycle,
+            setting,
+            sequence: AtomicU64::new(0),
+        }
+    }
+
+    /// Create a session generating the fuzzy events by `EventGen`, for a cluster managed outside
+    /// the session, which is never restarted
+    pub fn new_with_setting(config: ServerConfig, setting: FuzzySetting) -> Self {
+        Self::new_with_lifecycle(config, setting, Arc::new(LifecyclePhantom::default()))
+    }
+
+    /// Create a session generating the fuzzy events by `EventGen`, restarting the cluster by
+    /// `lifecycle` before each session
+    pub fn new_with_lifecycle(
+        config: ServerConfig,
+        setting: FuzzySetting,
+        lifecycle: Arc<dyn ClusterLifecycle>,
+    ) -> Self {
+        let generator = Arc::new(EventGen::new(config.peers.keys().cloned().collect(), setting.clone()));
+        Self::new(config, generator, Arc::new(InitializerPhantom::default()), lifecycle, setting)
+    }
+
+    pub fn config(&self) -> &ServerConfig {
+        &self.config
+    }
+
+    /// The index of the last session run or replayed, as passed to `ClusterLifecycle::restart`
+    pub fn last_session(&self) -> u64 {
+        self.sequence.load(Ordering::SeqCst).saturating_sub(1)
+    }
+
+    /// Stop the tested cluster by the lifecycle hook, after the last session
+    pub fn stop(&self) -> Res<()> {
+        self.lifecycle.stop()
+    }
+
+    /// Run a session for input `data`.
+    /// The database of the previous session is removed, and the tested nodes are restarted by
+    /// the lifecycle hook, so the recorded `action` table reproduces the run.
+    /// Return Ok after the input bytes were exhausted, or the error stopping the server.
+    pub fn run(&self, data: &[u8]) -> Res<()> {
+        self.run_with_db_path(data, self.config.db_path.clone())
+    }
+
+    /// Run a session for input `data`, recording the fuzzy events in the database `db_path`
+    pub fn run_with_db_path(&self, data: &[u8], db_path: String) -> Res<()> {
+        let _ = self.run_with_timeout(data, db_path, 0)?;
+        Ok(())
+    }
+
+    /// Run a session for input `data`, recording the fuzzy events in the database `db_path`.
+    /// The server is stopped if the input bytes were not exhausted after `timeout_ms`
+    /// milliseconds, 0 never stops the server.
+    /// Return false if the session timed out
+    pub fn run_with_timeout(&self, data: &[u8], db_path: String, timeout_ms: u64) -> Res<bool> {
+        let (server_addr, peers) = parse_address(&self.config)?;
+        if Path::new(&db_path).exists() {
+            fs::remove_file(&db_path).map_err(|e| { ET::IOError(e.to_string()) })?;
+        }
+        self.restart()?;
+        let notifier = Notifier::new();
+        let notify_end_data = Notifier::new();
+        let server = FuzzyServer::new_with_generator(
+            self.config.node_id,
+            format!("fuzzy_session_{}", self.config.node_id),
+            db_path,
+            notifier.clone(),
+            server_addr,
+            peers,
+            self.generator.clone(),
+            self.initializer.clone(),
+            self.setting.clone(),
+            data.to_vec(),
+            notify_end_data.clone(),
+        )?;
+        Self::run_server(server, notifier, notify_end_data, timeout_ms)
+    }
+
+    /// Replay the run recorded in the database `db_path`, the database is kept.
+    /// Return Ok after the last recorded delivery was sent.
+    pub fn replay_with_db_path(&self, db_path: String) -> Res<()> {
+        let (server_addr, peers) = parse_address(&self.config)?;
+        self.restart()?;
+        let notifier = Notifier::new();
+        let notify_end_data = Notifier::new();
+        let server = FuzzyServer::new_replay(
+            self.config.node_id,
+            format!("fuzzy_session_{}", self.config.node_id),
+            db_path,
+            notifier.clone(),
+            server_addr,
+            peers,
+            notify_end_data.clone(),
+        )?;
+        let _ = Self::run_server(server, notifier, notify_end_data, 0)?;
+        Ok(())
+    }
+
+    fn restart(&self) -> Res<()> {
+        let session = self.sequence.fetch_add(1, Ordering::SeqCst);
+        self.lifecycle.restart(session)
+    }
+
+    fn run_server(
+        server: FuzzyServer,
+        notifier: Notifier,
+        notify_end_data: Notifier,
+        timeout_ms: u64,
+    ) -> Res<bool> {
+        let start = Instant::now();
+        let join_handle = thread::spawn(move || {
+            server.run()
+        });
+        loop {
+            if notify_end_data.is_notified() {
+                let _ = notifier.notify_all();
+                let _ = join_handle.join();
+                return Ok(true);
+            }
+            if timeout_ms > 0 && start.elapsed() >= Duration::from_millis(timeout_ms) {
+                let _ = notifier.notify_all();
+                let _ = join_handle.join();
+                return Ok(false);
+            }
+            if join_handle.is_finished() {
+                return match join_handle.join() {
+                    Ok(Err(e)) => { Err(e) }
+                    Ok(Ok(())) => {
+                        Err(ET::FatalError("server stopped before the input bytes were exhausted".to_string()))
+                    }
+                    Err(_) => { Err(ET::FatalError("server panicked".to_string())) }
+                };
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+    }
+}
+
+fn parse_address(config: &ServerConfig) -> Res<(SocketAddr, HashMap<NID, SocketAddr>)> {
+    let server_addr = config.net_address.parse::<SocketAddr>().map_err(|e| {
+        ET::FatalError(format!("invalid server address {}: {}", config.net_address, e))
+    })?;
+    let mut peers = HashMap::new();
+    for (id, addr) in config.peers.iter() {
+        let a = addr.parse::<SocketAddr>().map_err(|e| {
+            ET::FatalError(format!("invalid address {} of node {}: {}", addr, id, e))
+        })?;
+        let _ = peers.insert(*id, a);
+    }
+    Ok((server_addr, peers))
+}
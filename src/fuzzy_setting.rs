@@ -1,10 +1,19 @@
+use scupt_util::message::Message;
 use scupt_util::node_id::NID;
 use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::fuzzy_scenario::FuzzyScenario;
+
+/// milliseconds after which an unfilled reorder window is released, if
+/// `message_reorder_flush_ms` is not set, so the last messages of a quiet link are not lost
+pub const DEFAULT_REORDER_FLUSH_MS: u64 = 100;
 
 #[derive(
     Serialize,
     Deserialize,
-    Clone)]
+    Clone,
+    Default)]
 pub struct FuzzySetting {
     /// return the possible crash/restart payload text
     pub crash_restart_payload: Vec<(String, String)>,
@@ -23,15 +32,324 @@ pub struct FuzzySetting {
 
     pub crash_ratio:f64,
 
+    /// ratio of the clock faults
+    #[serde(default)]
+    pub clock_fault_ratio:f64,
+
+    /// maximum milliseconds of a clock skew, forward or backward
+    #[serde(default)]
+    pub clock_max_skew_ms:u64,
+
+    /// maximum milliseconds of a clock pause
+    #[serde(default)]
+    pub clock_max_pause_ms:u64,
+
+    /// ratio of the faults of the disk operations
+    #[serde(default)]
+    pub disk_fault_ratio:f64,
+
+    /// ratio of the bidirectional partitions
     pub network_partition_ratio:f64,
 
+    /// ratio of the one-way partitions
+    #[serde(default)]
+    pub network_one_way_partition_ratio:f64,
+
+    /// ratio of the partitions splitting the nodes into two or more groups
+    #[serde(default)]
+    pub network_multi_partition_ratio:f64,
+
+    /// ratio of the failures of an arbitrary set of links
+    #[serde(default)]
+    pub network_link_failure_ratio:f64,
+
     pub partition_end_after_max_ms:u64,
 
+    /// maximum milliseconds between two steps of the background fault timeline, which crashes,
+    /// restarts and partitions any node regardless of the messages, 0 disables the timeline
+    #[serde(default)]
+    pub fault_interval_max_ms:u64,
+
+    /// maximum number of the nodes crashed at the same time, 0 is unbounded
+    #[serde(default)]
+    pub fault_max_crashed:u64,
+
+    /// maximum number of the nodes cut off from the largest connected group by all the
+    /// partitions and link failures in effect, 0 is unbounded
+    #[serde(default)]
+    pub fault_max_minority:u64,
+
+    /// maximum number of the crashes, partitions and link failures of a run, 0 is unbounded
+    #[serde(default)]
+    pub fault_max_total:u64,
+
     pub message_delay_ratio:f64,
 
     pub message_repeat_ratio:f64,
 
     pub message_lost_ratio:f64,
+
+    /// ratio of the messages with a mutated payload
+    #[serde(default)]
+    pub message_mutation_ratio:f64,
+
+    /// maximum number of the undelayed messages buffered on a link and released in a fuzzed
+    /// order, 0 or 1 disables reordering
+    #[serde(default)]
+    pub message_reorder_window:u64,
+
+    /// release the buffered messages of a link in their arrival order, if the window was not
+    /// filled after some milliseconds, 0 uses `DEFAULT_REORDER_FLUSH_MS`
+    #[serde(default)]
+    pub message_reorder_flush_ms:u64,
+
+    /// advance the virtual time by some milliseconds for each incoming message,
+    /// 0 sleeps in real time
+    #[serde(default)]
+    pub virtual_time_tick_ms:u64,
+
+    /// advance the virtual time to the next scheduled event, if no message arrived in some real
+    /// milliseconds, so the events after the last message are due even if the nodes are silent.
+    /// The advance depends on the wall clock, and breaks the reproducibility of the virtual time,
+    /// 0 disables it
+    #[serde(default)]
+    pub virtual_time_idle_ms:u64,
+
+    /// draw the decisions of each link from its own part of the fuzz input, so the same input
+    /// produces the same schedule regardless of the number of message receivers.
+    /// Each link has its own fault budget, and the session ends when the input of any link was
+    /// exhausted
+    #[serde(default)]
+    pub deterministic:bool,
+
+    /// tag each message delivered between two nodes by the action id delivering it, in the
+    /// `fuzzy_action_id` field of its JSON object, so an invariant violation can be traced to
+    /// the action. The delivered message then differs from the recorded one, and a node must
+    /// accept the extra field
+    #[serde(default)]
+    pub tag_action_id:bool,
+
+    /// the hand-written faults injected along with the fuzzed ones
+    #[serde(default)]
+    pub scenario:Option<FuzzyScenario>,
+
+    /// rules overriding the message ratios for the matching messages, a later matching rule
+    /// overrides an earlier one
+    #[serde(default)]
+    pub message_rule:Vec<MessageRule>,
 }
 
+/// A rule overriding the ratios of the messages it matches.
+/// A message matches if all the set conditions hold.
+#[derive(
+    Serialize,
+    Deserialize,
+    Clone,
+    Debug,
+    Default)]
+pub struct MessageRule {
+    /// JSON pointer of a value in the payload, e.g. `/AppendEntriesResp` for a variant of an
+    /// externally tagged enum, the payload is the `payload` field of the message if it has one
+    #[serde(default)]
+    pub path:Option<String>,
+
+    /// the value at `path` equals to this value
+    #[serde(default)]
+    pub value:Option<Value>,
 
+    #[serde(default)]
+    pub source:Option<NID>,
+
+    #[serde(default)]
+    pub dest:Option<NID>,
+
+    #[serde(default)]
+    pub message_delay_ratio:Option<f64>,
+
+    #[serde(default)]
+    pub message_lost_ratio:Option<f64>,
+
+    #[serde(default)]
+    pub message_repeat_ratio:Option<f64>,
+
+    /// ratio of crashing the dest of the message
+    #[serde(default)]
+    pub crash_ratio:Option<f64>,
+}
+
+/// The ratios of a message, the ratios of the setting overridden by the matching rules
+#[derive(
+    Clone,
+    Copy,
+    Debug)]
+pub struct MessageRatio {
+    pub delay:f64,
+    pub lost:f64,
+    pub repeat:f64,
+    pub crash:f64,
+}
+
+impl MessageRule {
+    /// Does the rule match a message from `source` to `dest`, `payload` is the parsed payload,
+    /// None if the message is not JSON
+    pub fn matches(&self, source: NID, dest: NID, payload: Option<&Value>) -> bool {
+        if let Some(s) = self.source {
+            if s != source {
+                return false;
+            }
+        }
+        if let Some(d) = self.dest {
+            if d != dest {
+                return false;
+            }
+        }
+        if !self.match_payload() {
+            return true;
+        }
+        let payload = match payload {
+            Some(p) => { p }
+            None => { return false; }
+        };
+        let path = self.path.clone().unwrap_or_default();
+        match (payload.pointer(path.as_str()), &self.value) {
+            (Some(v), Some(value)) => { v == value }
+            (Some(_), None) => { true }
+            (None, _) => { false }
+        }
+    }
+
+    fn match_payload(&self) -> bool {
+        self.path.is_some() || self.value.is_some()
+    }
+}
+
+impl FuzzySetting {
+    /// The ratios of a message, overridden by the matching rules.
+    /// The message is parsed once, and only if a rule matches the payload
+    pub fn message_ratio(&self, message: &Message<String>) -> MessageRatio {
+        let mut ratio = MessageRatio {
+            delay: self.message_delay_ratio,
+            lost: self.message_lost_ratio,
+            repeat: self.message_repeat_ratio,
+            crash: self.crash_ratio,
+        };
+        if self.message_rule.is_empty() {
+            return ratio;
+        }
+        let json: Option<Value> = if self.message_rule.iter().any(|r| { r.match_payload() }) {
+            serde_json::from_str(message.clone().payload().as_str()).ok()
+        } else {
+            None
+        };
+        let payload = json.as_ref().map(|j| {
+            match j.get("payload") {
+                Some(p) => { p }
+                None => { j }
+            }
+        });
+        for rule in self.message_rule.iter() {
+            if !rule.matches(message.source(), message.dest(), payload) {
+                continue;
+            }
+            if let Some(r) = rule.message_delay_ratio {
+                ratio.delay = r;
+            }
+            if let Some(r) = rule.message_lost_ratio {
+                ratio.lost = r;
+            }
+            if let Some(r) = rule.message_repeat_ratio {
+                ratio.repeat = r;
+            }
+            if let Some(r) = rule.crash_ratio {
+                ratio.crash = r;
+            }
+        }
+        ratio
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use scupt_util::message::Message;
+    use serde_json::{json, Value};
+
+    use crate::fuzzy_setting::{FuzzySetting, MessageRule};
+
+    #[test]
+    fn test_rule_match_node() {
+        let rule = MessageRule {
+            source: Some(1),
+            dest: Some(2),
+            ..Default::default()
+        };
+        assert!(rule.matches(1, 2, None));
+        assert!(!rule.matches(2, 1, None));
+        assert!(!rule.matches(1, 3, None));
+        assert!(MessageRule::default().matches(3, 4, None));
+    }
+
+    #[test]
+    fn test_rule_match_payload() {
+        let payload = json!({"AppendEntriesResp": {"term": 2}});
+        let variant = MessageRule {
+            path: Some("/AppendEntriesResp".to_string()),
+            ..Default::default()
+        };
+        assert!(variant.matches(1, 2, Some(&payload)));
+        assert!(!variant.matches(1, 2, Some(&json!({"Vote": {}}))));
+        assert!(!variant.matches(1, 2, None));
+
+        let term = MessageRule {
+            path: Some("/AppendEntriesResp/term".to_string()),
+            value: Some(json!(2)),
+            ..Default::default()
+        };
+        assert!(term.matches(1, 2, Some(&payload)));
+        assert!(!term.matches(1, 2, Some(&json!({"AppendEntriesResp": {"term": 3}}))));
+
+        // a value without a path is compared with the whole payload
+        let whole = MessageRule {
+            value: Some(Value::String("ping".to_string())),
+            ..Default::default()
+        };
+        assert!(whole.matches(1, 2, Some(&json!("ping"))));
+        assert!(!whole.matches(1, 2, Some(&json!("pong"))));
+    }
+
+    #[test]
+    fn test_message_ratio() {
+        let mut setting = FuzzySetting::default();
+        setting.message_lost_ratio = 0.1;
+        setting.message_delay_ratio = 0.2;
+        setting.message_rule = vec![
+            MessageRule {
+                path: Some("/AppendEntriesResp".to_string()),
+                message_lost_ratio: Some(0.5),
+                message_delay_ratio: Some(0.6),
+                ..Default::default()
+            },
+            MessageRule {
+                dest: Some(2),
+                message_lost_ratio: Some(0.9),
+                ..Default::default()
+            },
+        ];
+
+        // the payload field of the message is matched
+        let wrapped = json!({"payload": {"AppendEntriesResp": {"term": 1}}}).to_string();
+        let ratio = setting.message_ratio(&Message::new(wrapped.clone(), 1, 3));
+        assert_eq!(ratio.lost, 0.5);
+        assert_eq!(ratio.delay, 0.6);
+
+        // the later rule overrides the lost ratio of the earlier one
+        let ratio = setting.message_ratio(&Message::new(wrapped, 1, 2));
+        assert_eq!(ratio.lost, 0.9);
+        assert_eq!(ratio.delay, 0.6);
+
+        // a message matching no rule keeps the ratios of the setting
+        let other = json!({"Vote": {}}).to_string();
+        let ratio = setting.message_ratio(&Message::new(other, 1, 3));
+        assert_eq!(ratio.lost, 0.1);
+        assert_eq!(ratio.delay, 0.2);
+    }
+}
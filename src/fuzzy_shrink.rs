@@ -0,0 +1,196 @@
+use std::fs;
+use std::path::Path;
+use std::sync::Arc;
+
+use rusqlite::Connection;
+use scupt_util::error_type::ET;
+use scupt_util::res::Res;
+use scupt_util::res_of::res_sqlite;
+use tracing::info;
+
+use crate::fuzzy_session::FuzzySession;
+
+/// Decide whether a run of the tested nodes failed
+pub trait FailurePredicate: Send + Sync {
+    /// Is the run `session` failed.
+    /// `session` is the index passed to `ClusterLifecycle::restart` before the run, by which the
+    /// nodes can report their violations, e.g. to a file named by the index.
+    /// `db_path` is the database of the run, which a replay reads its schedule from and does not
+    /// write to, so the outcome of a replay is only known by the nodes
+    fn is_failure(&self, session: u64, db_path: &str) -> Res<bool>;
+}
+
+/// Minimize a failing run.
+/// The shrinker reruns the cluster with reduced fuzz inputs, or replays reduced recorded
+/// schedules, and keeps only the reductions still failing by the predicate.
+/// The runs are recorded in the databases of the directory `work_dir`, the session restarts the
+/// cluster by its lifecycle hook before each run.
+pub struct FuzzyShrinker {
+    session: Arc<FuzzySession>,
+    predicate: Arc<dyn FailurePredicate>,
+    work_dir: String,
+}
+
+impl FuzzyShrinker {
+    pub fn new(
+        session: Arc<FuzzySession>,
+        predicate: Arc<dyn FailurePredicate>,
+        work_dir: String,
+    ) -> Self {
+        Self {
+            session,
+            predicate,
+            work_dir,
+        }
+    }
+
+    /// Shrink the fuzz input `data` of a failing run.
+    /// Return the minimal failing input, its run is recorded in the database `output_db_path`
+    pub fn shrink_input(&self, data: &[u8], output_db_path: &str) -> Res<Vec<u8>> {
+        let db_path = self.work_path("shrink_input.db")?;
+        let minimal = ddmin(data.to_vec(), |candidate| {
+            self.input_fails(candidate, &db_path)
+        })?;
+        // rerun the minimal input, so the output database records its run
+        if !self.input_fails(&minimal, &db_path)? {
+            return Err(ET::FatalError("the minimal input does not fail again".to_string()));
+        }
+        copy_db(&db_path, output_db_path)?;
+        Ok(minimal)
+    }
+
+    /// Shrink the recorded schedule of the database `db_path` of a failing run.
+    /// The deliveries are removed from the schedule while its replay still fails, the minimal
+    /// schedule is written to the database `output_db_path`, which only keeps the actions of the
+    /// remaining deliveries.
+    /// Return the number of the remaining deliveries
+    pub fn shrink_schedule(&self, db_path: &str, output_db_path: &str) -> Res<usize> {
+        let candidate_path = self.work_path("shrink_schedule.db")?;
+        let deliveries = load_delivery_id(db_path)?;
+        let minimal = ddmin(deliveries.clone(), |candidate| {
+            write_schedule(db_path, &candidate_path, &deliveries, candidate)?;
+            self.replay_fails(&candidate_path)
+        })?;
+        write_schedule(db_path, output_db_path, &deliveries, &minimal)?;
+        prune_action(output_db_path)?;
+        Ok(minimal.len())
+    }
+
+    fn input_fails(&self, data: &[u8], db_path: &str) -> Res<bool> {
+        self.session.run_with_db_path(data, db_path.to_string())?;
+        self.predicate.is_failure(self.session.last_session(), db_path)
+    }
+
+    fn replay_fails(&self, db_path: &str) -> Res<bool> {
+        self.session.replay_with_db_path(db_path.to_string())?;
+        self.predicate.is_failure(self.session.last_session(), db_path)
+    }
+
+    fn work_path(&self, name: &str) -> Res<String> {
+        fs::create_dir_all(&self.work_dir).map_err(|e| { ET::IOError(e.to_string()) })?;
+        let path = Path::new(&self.work_dir).join(name);
+        Ok(path.to_string_lossy().to_string())
+    }
+}
+
+/// Delta debugging, remove chunks of `input` while `fails` holds for the remaining items,
+/// halving the chunk size when no chunk can be removed.
+/// `fails` must hold for `input`
+fn ddmin<T: Clone, F: FnMut(&Vec<T>) -> Res<bool>>(input: Vec<T>, mut fails: F) -> Res<Vec<T>> {
+    if !fails(&input)? {
+        return Err(ET::FatalError("the run to shrink does not fail".to_string()));
+    }
+    let mut input = input;
+    let mut n = 2;
+    while !input.is_empty() {
+        let chunk = (input.len() + n - 1) / n;
+        let mut reduced = false;
+        for i in 0..n {
+            let start = i * chunk;
+            if start >= input.len() {
+                break;
+            }
+            let end = (start + chunk).min(input.len());
+            let mut candidate = input[..start].to_vec();
+            candidate.extend_from_slice(&input[end..]);
+            if fails(&candidate)? {
+                info!("shrink from {} to {} items", input.len(), candidate.len());
+                input = candidate;
+                n = (n - 1).max(2);
+                reduced = true;
+                break;
+            }
+        }
+        if !reduced {
+            if n >= input.len() {
+                break;
+            }
+            n = (n * 2).min(input.len());
+        }
+    }
+    Ok(input)
+}
+
+fn copy_db(from: &str, to: &str) -> Res<()> {
+    let _ = fs::copy(from, to).map_err(|e| { ET::IOError(e.to_string()) })?;
+    Ok(())
+}
+
+fn load_delivery_id(db_path: &str) -> Res<Vec<u64>> {
+    let conn = res_sqlite(Connection::open(db_path))?;
+    let mut stmt = res_sqlite(conn.prepare("select id from delivery order by id"))?;
+    let rows = res_sqlite(stmt.query_map([], |row| { row.get::<_, u64>(0) }))?;
+    let mut vec = vec![];
+    for r in rows {
+        vec.push(res_sqlite(r)?);
+    }
+    Ok(vec)
+}
+
+/// Copy the database `from` to `to`, removing the deliveries not in `kept`
+fn write_schedule(from: &str, to: &str, all: &Vec<u64>, kept: &Vec<u64>) -> Res<()> {
+    copy_db(from, to)?;
+    let mut conn = res_sqlite(Connection::open(to))?;
+    let trans = res_sqlite(conn.transaction())?;
+    let mut i = 0;
+    for id in all.iter() {
+        if i < kept.len() && kept[i] == *id {
+            i += 1;
+            continue;
+        }
+        let _ = res_sqlite(trans.execute("delete from delivery where id = ?1", (id, )))?;
+    }
+    res_sqlite(trans.commit())?;
+    Ok(())
+}
+
+/// Remove the actions without any delivery
+fn prune_action(db_path: &str) -> Res<()> {
+    let conn = res_sqlite(Connection::open(db_path))?;
+    let _ = res_sqlite(conn.execute(
+        "delete from action where id not in (select action_id from delivery)", ()))?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::fuzzy_shrink::ddmin;
+
+    #[test]
+    fn test_ddmin_minimal_failing() {
+        let input: Vec<u64> = (0..16).collect();
+        let mut runs = 0;
+        let minimal = ddmin(input, |v| {
+            runs += 1;
+            Ok(v.contains(&3) && v.contains(&11))
+        }).unwrap();
+        assert_eq!(minimal, vec![3, 11]);
+        assert!(runs < 16 * 16);
+    }
+
+    #[test]
+    fn test_ddmin_not_failing() {
+        let r = ddmin(vec![1, 2, 3], |_| { Ok(false) });
+        assert!(r.is_err());
+    }
+}
@@ -7,7 +7,17 @@ pub mod fuzzy_event;
 pub mod fuzzy;
 pub mod fuzzy_client;
 mod fuzzy_driver;
+mod payload_mutation;
+mod virtual_clock;
+mod fault_budget;
 pub mod fuzzy_server;
 pub mod server_config;
 pub mod initializer;
-mod fuzzy_setting;
+pub mod lifecycle;
+pub mod fuzzy_setting;
+pub mod fuzzy_session;
+pub mod fuzzy_clock;
+pub mod fuzzy_shrink;
+pub mod fuzzy_corpus;
+pub mod fuzzy_campaign;
+pub mod fuzzy_scenario;
@@ -0,0 +1,30 @@
+use scupt_util::res::Res;
+
+/// The lifecycle of the tested cluster, provided by the user
+pub trait ClusterLifecycle: Send + Sync {
+    /// Start or restart the tested nodes before the session `session`, so no state is carried
+    /// over from the previous session. The nodes must be listening on the addresses of
+    /// `ServerConfig::peers` after it returned
+    fn restart(&self, session: u64) -> Res<()>;
+
+    /// Stop the tested nodes after the last session
+    fn stop(&self) -> Res<()> {
+        Ok(())
+    }
+}
+
+/// A lifecycle of a cluster managed outside the fuzzy server, which is never restarted
+#[derive(Clone)]
+pub struct LifecyclePhantom {}
+
+impl Default for LifecyclePhantom {
+    fn default() -> Self {
+        Self {}
+    }
+}
+
+impl ClusterLifecycle for LifecyclePhantom {
+    fn restart(&self, _session: u64) -> Res<()> {
+        Ok(())
+    }
+}
@@ -0,0 +1,98 @@
+use std::fs;
+use std::path::PathBuf;
+use std::process::exit;
+
+use clap::{Args, Parser, Subcommand};
+use scupt_fuzzy::fuzzy_session::FuzzySession;
+use scupt_fuzzy::fuzzy_setting::FuzzySetting;
+use scupt_fuzzy::server_config::ServerConfig;
+use serde::de::DeserializeOwned;
+
+/// the input bytes were exhausted
+const EXIT_END_DATA: i32 = 0;
+
+/// the configuration, setting or input file cannot be loaded
+const EXIT_INVALID_INPUT: i32 = 1;
+
+/// the address is invalid, or the server stopped before the input bytes were exhausted
+const EXIT_SERVER_ERROR: i32 = 2;
+
+#[derive(Parser)]
+#[command(name = "scupt-fuzzy", about = "Fuzz testing server for distributed systems")]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// run a fuzzy server until the input bytes are exhausted
+    Serve(ServeArgs),
+}
+
+#[derive(Args)]
+struct ServeArgs {
+    /// server configuration file, in TOML or JSON format
+    #[arg(short, long)]
+    config: PathBuf,
+
+    /// fuzzy setting file, in TOML or JSON format
+    #[arg(short, long)]
+    setting: PathBuf,
+
+    /// input corpus file, the raw bytes driving the fuzzy events
+    #[arg(short, long)]
+    input: PathBuf,
+}
+
+fn main() {
+    let cli = Cli::parse();
+    let code = match cli.command {
+        Command::Serve(args) => { serve(args) }
+    };
+    exit(code);
+}
+
+fn serve(args: ServeArgs) -> i32 {
+    let r = load_serve_args(&args);
+    let (config, setting, data) = match r {
+        Ok(t) => { t }
+        Err(e) => {
+            eprintln!("{}", e);
+            return EXIT_INVALID_INPUT;
+        }
+    };
+    let session = FuzzySession::new_with_setting(config, setting);
+    match session.run(data.as_slice()) {
+        Ok(()) => { EXIT_END_DATA }
+        Err(e) => {
+            eprintln!("server error: {:?}", e);
+            EXIT_SERVER_ERROR
+        }
+    }
+}
+
+fn load_serve_args(args: &ServeArgs) -> Result<(ServerConfig, FuzzySetting, Vec<u8>), String> {
+    let config = load_file::<ServerConfig>(&args.config)?;
+    let setting = load_file::<FuzzySetting>(&args.setting)?;
+    let data = fs::read(&args.input).map_err(|e| {
+        format!("read input file {} error: {}", args.input.display(), e)
+    })?;
+    Ok((config, setting, data))
+}
+
+/// load a TOML file if the extension is `.toml`, otherwise a JSON file
+fn load_file<T: DeserializeOwned>(path: &PathBuf) -> Result<T, String> {
+    let s = fs::read_to_string(path).map_err(|e| {
+        format!("read file {} error: {}", path.display(), e)
+    })?;
+    let is_toml = path.extension().map_or(false, |e| { e == "toml" });
+    let r = if is_toml {
+        toml::from_str::<T>(s.as_str()).map_err(|e| { e.to_string() })
+    } else {
+        serde_json::from_str::<T>(s.as_str()).map_err(|e| { e.to_string() })
+    };
+    r.map_err(|e| {
+        format!("parse file {} error: {}", path.display(), e)
+    })
+}
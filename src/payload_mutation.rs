@@ -0,0 +1,190 @@
+use arbitrary::{Arbitrary, Unstructured};
+use serde_json::{Map, Value};
+
+/// Candidates of the mutations in a JSON value, each candidate is located by a JSON pointer
+#[derive(Default)]
+struct Candidate {
+    /// pointers of the numbers
+    number: Vec<String>,
+    /// pointers of the objects, and a field name of the object
+    field: Vec<(String, String)>,
+    /// pointers of the externally tagged enum values, an object with a single field
+    /// or a string, and the variant name
+    variant: Vec<(String, String)>,
+}
+
+/// Mutate the payload of a JSON message, by flipping a number, dropping a field, or swapping an
+/// enum variant.
+/// If the message is an object with a `payload` field, only the payload is mutated.
+/// Return None if the text is not JSON, or nothing can be mutated by the chosen mutation.
+pub fn mutate_payload(json: &str, u: &mut Unstructured) -> arbitrary::Result<Option<String>> {
+    let mut value: Value = match serde_json::from_str(json) {
+        Ok(v) => { v }
+        Err(_) => { return Ok(None); }
+    };
+    let root = if value.get("payload").is_some() {
+        "/payload".to_string()
+    } else {
+        "".to_string()
+    };
+    let mut candidate = Candidate::default();
+    match value.pointer(root.as_str()) {
+        Some(v) => { collect(v, root, &mut candidate); }
+        None => { return Ok(None); }
+    }
+    let mutated = match u8::arbitrary(u)? % 3 {
+        0 => { flip_number(&mut value, &candidate, u)? }
+        1 => { drop_field(&mut value, &candidate, u)? }
+        _ => { swap_variant(&mut value, &candidate, u)? }
+    };
+    if mutated {
+        Ok(Some(serde_json::to_string(&value).unwrap()))
+    } else {
+        Ok(None)
+    }
+}
+
+fn escape(key: &str) -> String {
+    key.replace('~', "~0").replace('/', "~1")
+}
+
+fn collect(value: &Value, path: String, candidate: &mut Candidate) {
+    match value {
+        Value::Number(_) => {
+            candidate.number.push(path);
+        }
+        Value::String(s) => {
+            candidate.variant.push((path, s.clone()));
+        }
+        Value::Array(array) => {
+            for (i, v) in array.iter().enumerate() {
+                collect(v, format!("{}/{}", path, i), candidate);
+            }
+        }
+        Value::Object(object) => {
+            if object.len() == 1 {
+                for k in object.keys() {
+                    candidate.variant.push((path.clone(), k.clone()));
+                }
+            }
+            for (k, v) in object.iter() {
+                candidate.field.push((path.clone(), k.clone()));
+                collect(v, format!("{}/{}", path, escape(k)), candidate);
+            }
+        }
+        _ => {}
+    }
+}
+
+fn choose<'a, T>(vec: &'a Vec<T>, u: &mut Unstructured) -> arbitrary::Result<Option<&'a T>> {
+    if vec.is_empty() {
+        return Ok(None);
+    }
+    let i = u32::arbitrary(u)? as usize % vec.len();
+    Ok(vec.get(i))
+}
+
+fn flip_number(value: &mut Value, candidate: &Candidate, u: &mut Unstructured) -> arbitrary::Result<bool> {
+    let path = match choose(&candidate.number, u)? {
+        Some(p) => { p }
+        None => { return Ok(false); }
+    };
+    let n = match value.pointer_mut(path.as_str()) {
+        Some(n) => { n }
+        None => { return Ok(false); }
+    };
+    let i = n.as_i64().unwrap_or(0);
+    let flipped = match u8::arbitrary(u)? % 4 {
+        0 => { 0 }
+        1 => { i.wrapping_neg() }
+        2 => { i64::MAX }
+        _ => { i64::arbitrary(u)? }
+    };
+    *n = Value::from(flipped);
+    Ok(true)
+}
+
+fn drop_field(value: &mut Value, candidate: &Candidate, u: &mut Unstructured) -> arbitrary::Result<bool> {
+    let (path, key) = match choose(&candidate.field, u)? {
+        Some(p) => { p }
+        None => { return Ok(false); }
+    };
+    let opt = value.pointer_mut(path.as_str()).and_then(|v| { v.as_object_mut() });
+    match opt {
+        Some(object) => { Ok(object.remove(key).is_some()) }
+        None => { Ok(false) }
+    }
+}
+
+fn swap_variant(value: &mut Value, candidate: &Candidate, u: &mut Unstructured) -> arbitrary::Result<bool> {
+    let (path, name) = match choose(&candidate.variant, u)? {
+        Some(p) => { p }
+        None => { return Ok(false); }
+    };
+    let mut names: Vec<String> = candidate.variant.iter()
+        .map(|(_, n)| { n.clone() })
+        .filter(|n| { n != name })
+        .collect();
+    names.sort();
+    names.dedup();
+    let new_name = match choose(&names, u)? {
+        Some(n) => { n.clone() }
+        None => { return Ok(false); }
+    };
+    let v = match value.pointer_mut(path.as_str()) {
+        Some(v) => { v }
+        None => { return Ok(false); }
+    };
+    match v {
+        Value::String(s) => {
+            *s = new_name;
+        }
+        Value::Object(object) => {
+            let inner = object.remove(name).unwrap_or(Value::Null);
+            let mut swapped = Map::new();
+            let _ = swapped.insert(new_name, inner);
+            *object = swapped;
+        }
+        _ => { return Ok(false); }
+    }
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use arbitrary::Unstructured;
+    use serde_json::{json, Value};
+
+    use crate::payload_mutation::mutate_payload;
+
+    fn mutate(json: &str, bytes: &[u8]) -> Option<Value> {
+        let mut u = Unstructured::new(bytes);
+        let opt = mutate_payload(json, &mut u).unwrap();
+        opt.map(|s| { serde_json::from_str(s.as_str()).unwrap() })
+    }
+
+    #[test]
+    fn test_flip_number() {
+        let v = mutate(r#"{"source":1,"payload":{"n":5}}"#, &[0, 0, 0, 0, 0, 1]);
+        assert_eq!(v, Some(json!({"source":1,"payload":{"n":-5}})));
+    }
+
+    #[test]
+    fn test_drop_field() {
+        let v = mutate(r#"{"payload":{"a":1,"b":true}}"#, &[1, 0, 0, 0, 0]);
+        assert_eq!(v, Some(json!({"payload":{"b":true}})));
+    }
+
+    #[test]
+    fn test_swap_variant() {
+        let v = mutate(r#"{"payload":["A",{"B":1}]}"#, &[2, 0, 0, 0, 0, 0, 0, 0, 0]);
+        assert_eq!(v, Some(json!({"payload":["B",{"B":1}]})));
+        let v = mutate(r#"{"payload":["A",{"B":1}]}"#, &[2, 1, 1, 1, 1, 0, 0, 0, 0]);
+        assert_eq!(v, Some(json!({"payload":["A",{"A":1}]})));
+    }
+
+    #[test]
+    fn test_not_json() {
+        assert_eq!(mutate("not json", &[0]), None);
+    }
+}
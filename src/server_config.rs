@@ -1,7 +1,8 @@
 use std::collections::HashMap;
 
 use scupt_util::node_id::NID;
-use serde::{Deserialize, Serialize};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use serde::de::Error;
 
 #[derive(
 Clone,
@@ -13,5 +14,46 @@ pub struct ServerConfig {
     pub db_path: String,
     pub node_id: NID,
     pub net_address: String,
+    /// the addresses of the tested nodes, the keys are node ids written as strings, as the
+    /// keys of a TOML table or a JSON object are always strings
+    #[serde(serialize_with = "serialize_peers", deserialize_with = "deserialize_peers")]
     pub peers: HashMap<NID, String>,
-}
\ No newline at end of file
+}
+
+fn serialize_peers<S: Serializer>(peers: &HashMap<NID, String>, serializer: S) -> Result<S::Ok, S::Error> {
+    let map: HashMap<String, &String> = peers.iter().map(|(id, addr)| {
+        (id.to_string(), addr)
+    }).collect();
+    map.serialize(serializer)
+}
+
+fn deserialize_peers<'de, D: Deserializer<'de>>(deserializer: D) -> Result<HashMap<NID, String>, D::Error> {
+    let map = HashMap::<String, String>::deserialize(deserializer)?;
+    let mut peers = HashMap::new();
+    for (id, addr) in map {
+        let nid = id.parse::<NID>().map_err(|e| {
+            D::Error::custom(format!("invalid node id {}: {}", id, e))
+        })?;
+        let _ = peers.insert(nid, addr);
+    }
+    Ok(peers)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::server_config::ServerConfig;
+
+    #[test]
+    fn test_load_example_config() {
+        let c1: ServerConfig = toml::from_str(include_str!("../example/server.toml")).unwrap();
+        let c2: ServerConfig = serde_json::from_str(include_str!("../example/server.json")).unwrap();
+        for c in [&c1, &c2] {
+            assert_eq!(c.node_id, 100);
+            assert_eq!(c.peers.len(), 3);
+            assert_eq!(c.peers.get(&2).unwrap(), "127.0.0.1:8002");
+        }
+        let s = toml::to_string(&c1).unwrap();
+        let c3: ServerConfig = toml::from_str(s.as_str()).unwrap();
+        assert_eq!(c3.peers, c1.peers);
+    }
+}
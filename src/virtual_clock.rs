@@ -0,0 +1,122 @@
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap};
+use std::sync::Mutex;
+use std::time::Instant;
+
+use crate::fuzzy_event::FuzzyEvent;
+
+/// A simulated clock, the delayed events are kept in a priority queue ordered by their virtual
+/// time, and executed when the virtual time advances, instead of sleeping in real time.
+/// The events scheduled at the same virtual time are executed in their scheduling order.
+pub struct VirtualClock {
+    inner: Mutex<VirtualClockInner>,
+}
+
+struct VirtualClockInner {
+    now_ms: u64,
+    sequence: u64,
+    /// (virtual time, sequence) of the scheduled events
+    queue: BinaryHeap<Reverse<(u64, u64)>>,
+    /// scheduled events, (action id, event) indexed by sequence
+    event: HashMap<u64, (u64, FuzzyEvent)>,
+    /// real time of the last advance
+    last_advance: Instant,
+}
+
+impl VirtualClock {
+    pub fn new() -> Self {
+        Self {
+            inner: Mutex::new(VirtualClockInner {
+                now_ms: 0,
+                sequence: 0,
+                queue: Default::default(),
+                event: Default::default(),
+                last_advance: Instant::now(),
+            }),
+        }
+    }
+
+    pub fn now_ms(&self) -> u64 {
+        let inner = self.inner.lock().unwrap();
+        inner.now_ms
+    }
+
+    /// Schedule an event `delay_ms` virtual milliseconds later
+    pub fn schedule(&self, delay_ms: u64, id: u64, event: FuzzyEvent) {
+        let mut inner = self.inner.lock().unwrap();
+        let time = inner.now_ms + delay_ms;
+        let sequence = inner.sequence;
+        inner.sequence += 1;
+        inner.queue.push(Reverse((time, sequence)));
+        let _ = inner.event.insert(sequence, (id, event));
+    }
+
+    /// Advance the virtual time by `ms`, return the events due, in order
+    pub fn advance(&self, ms: u64) -> Vec<(u64, FuzzyEvent)> {
+        let mut inner = self.inner.lock().unwrap();
+        inner.now_ms += ms;
+        inner.last_advance = Instant::now();
+        inner.pop_due()
+    }
+
+    /// Advance the virtual time to the next scheduled event, if the clock was not advanced in
+    /// the last `idle_ms` real milliseconds, return the events due, in order
+    pub fn advance_if_idle(&self, idle_ms: u64) -> Vec<(u64, FuzzyEvent)> {
+        let mut inner = self.inner.lock().unwrap();
+        if (inner.last_advance.elapsed().as_millis() as u64) < idle_ms {
+            return vec![];
+        }
+        let next = match inner.queue.peek() {
+            Some(Reverse((time, _))) => { *time }
+            None => { return vec![]; }
+        };
+        if next > inner.now_ms {
+            inner.now_ms = next;
+        }
+        inner.last_advance = Instant::now();
+        inner.pop_due()
+    }
+}
+
+impl VirtualClockInner {
+    fn pop_due(&mut self) -> Vec<(u64, FuzzyEvent)> {
+        let mut vec = vec![];
+        loop {
+            let sequence = match self.queue.peek() {
+                Some(Reverse((time, sequence))) => {
+                    if *time > self.now_ms {
+                        break;
+                    }
+                    *sequence
+                }
+                None => { break; }
+            };
+            let _ = self.queue.pop();
+            if let Some(e) = self.event.remove(&sequence) {
+                vec.push(e);
+            }
+        }
+        vec
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::fuzzy_event::FuzzyEvent;
+    use crate::virtual_clock::VirtualClock;
+
+    #[test]
+    fn test_equal_time_in_scheduling_order() {
+        let clock = VirtualClock::new();
+        clock.schedule(20, 1, FuzzyEvent::ReorderRelease(vec![]));
+        clock.schedule(10, 2, FuzzyEvent::ReorderRelease(vec![]));
+        clock.schedule(20, 3, FuzzyEvent::ReorderRelease(vec![]));
+        clock.schedule(10, 4, FuzzyEvent::ReorderRelease(vec![]));
+        assert!(clock.advance(5).is_empty());
+        let ids: Vec<u64> = clock.advance(5).iter().map(|(id, _)| { *id }).collect();
+        assert_eq!(ids, vec![2, 4]);
+        let ids: Vec<u64> = clock.advance(10).iter().map(|(id, _)| { *id }).collect();
+        assert_eq!(ids, vec![1, 3]);
+        assert_eq!(clock.now_ms(), 20);
+    }
+}